@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
+
+use libpso::crypto::PSOCipher;
+use libpso::crypto::pc::PSOPCCipher;
+use libpso::crypto::gc::PSOGCCipher;
+use libpso::crypto::bb::{PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+
+// a small lobby packet, SendKeyAndTeamSettings and 0xE7 full character data
+const PACKET_SIZES: [usize; 3] = [0x40, 0xAF8, 0x39A8];
+
+fn bb_cipher() -> PSOBBCipher {
+    let key_file = (0..BB_KEY_FILE_SIZE).map(|i| (i * 31 + 1) as u8).collect::<Vec<_>>();
+    PSOBBCipher::with_key(&BBKeyTable::from_bytes(&key_file).unwrap(), [0x55; 48])
+}
+
+fn bench_cipher<C: PSOCipher>(c: &mut Criterion, name: &str, mut cipher: C) {
+    let mut group = c.benchmark_group(name);
+    for size in PACKET_SIZES.iter() {
+        let mut data = vec![0xAAu8; *size];
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt_in_place", size), size, |b, _| {
+            b.iter(|| cipher.encrypt_in_place(&mut data).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("encrypt", size), size, |b, _| {
+            b.iter(|| cipher.encrypt(&data).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn pc(c: &mut Criterion) {
+    bench_cipher(c, "pc", PSOPCCipher::new(0x12345678));
+}
+
+fn gc(c: &mut Criterion) {
+    bench_cipher(c, "gc", PSOGCCipher::new(0x12345678));
+}
+
+fn bb(c: &mut Criterion) {
+    bench_cipher(c, "bb", bb_cipher());
+
+    let cipher = bb_cipher();
+    let mut packets = vec![vec![0xAAu8; 0xAF8]; 12];
+    let mut group = c.benchmark_group("bb");
+    group.throughput(Throughput::Bytes((packets.len() * 0xAF8) as u64));
+    group.bench_function("encrypt_batch/12x0xAF8", |b| {
+        b.iter(|| {
+            let mut bufs = packets.iter_mut().map(|p| p.as_mut_slice()).collect::<Vec<_>>();
+            cipher.encrypt_batch(&mut bufs).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bb_key_setup(c: &mut Criterion) {
+    c.bench_function("bb/new", |b| b.iter(bb_cipher));
+}
+
+criterion_group!(benches, pc, gc, bb, bb_key_setup);
+criterion_main!(benches);
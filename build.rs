@@ -0,0 +1,19 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "ffi")]
+    {
+        let crate_dir = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+        let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+        let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+            .expect("unable to read cbindgen.toml");
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(crate_dir.join("src").join("ffi.rs"))
+            .generate()
+            .expect("unable to generate ffi bindings")
+            .write_to_file(out_dir.join("libpso.h"));
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+    }
+}
@@ -0,0 +1,38 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use libpso::crypto::PSOCipher;
+use libpso::crypto::pc::PSOPCCipher;
+use libpso::crypto::gc::PSOGCCipher;
+use libpso::crypto::bb::{PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+use libpso::crypto::detect::{CipherDetector, WelcomeKey};
+
+fn crypt<C: PSOCipher + Clone>(cipher: C, data: &[u8]) {
+    if let Ok(enc) = cipher.clone().encrypt(data) {
+        assert!(cipher.clone().decrypt(&enc).unwrap() == data);
+    }
+    let _ = cipher.clone().decrypt(data);
+}
+
+fuzz_target!(|data: &[u8]| {
+    // the first 4 bytes seed the v2/v3 ciphers and the first 48 the bb one
+    let mut seed = [0u8; 48];
+    for (dst, src) in seed.iter_mut().zip(data) {
+        *dst = *src;
+    }
+    let key = u32::from_le_bytes([seed[0], seed[1], seed[2], seed[3]]);
+
+    crypt(PSOPCCipher::new(key), data);
+    crypt(PSOGCCipher::new(key), data);
+    let _ = PSOPCCipher::from_bytes(data);
+    let _ = PSOGCCipher::from_bytes(data);
+
+    let _ = BBKeyTable::from_bytes(data);
+    let _ = PSOBBCipher::from_bytes(data);
+    let key_file: Vec<u8> = data.iter().cycle().take(BB_KEY_FILE_SIZE).cloned().collect();
+    let table = BBKeyTable::from_bytes(&key_file).unwrap_or_else(|_| BBKeyTable::from_bytes(&[0; BB_KEY_FILE_SIZE]).unwrap());
+    crypt(PSOBBCipher::with_key(&table, seed), data);
+
+    let detector = CipherDetector::new().with_bb_key(table);
+    let _ = detector.detect(WelcomeKey::V2V3(key), data);
+    let _ = detector.detect(WelcomeKey::BB(seed), data);
+});
@@ -0,0 +1,19 @@
+// anything that parses has to be written back out to bytes that parse to the same packet. a
+// packet that came in unpadded goes back out padded, and a String or remaining Vec at the end
+// reads that padding as data, so then the written bytes only have to be stable.
+macro_rules! parse {
+    ($data:expr, $($packet:ty),*) => {
+        $(
+            if let Ok(pkt) = <$packet as ::libpso::PSOPacket>::from_bytes($data) {
+                let bytes = ::libpso::PSOPacket::as_bytes(&pkt);
+                let again = <$packet as ::libpso::PSOPacket>::from_bytes(&bytes).unwrap();
+                if bytes.len() == $data.len() {
+                    assert!(again == pkt);
+                }
+                else {
+                    assert!(::libpso::PSOPacket::as_bytes(&again) == bytes);
+                }
+            }
+        )*
+    }
+}
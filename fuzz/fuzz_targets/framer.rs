@@ -0,0 +1,47 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use libpso::HeaderKind;
+use libpso::crypto::{PSOCipher, NullCipher};
+use libpso::crypto::pc::PSOPCCipher;
+use libpso::crypto::gc::PSOGCCipher;
+use libpso::crypto::bb::{PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+use libpso::net::framer::PacketFramer;
+
+// after the first byte the data is a run of chunks, each a length byte and then that many bytes
+// to push. every packet that comes out has to be exactly as long as its header says.
+fn frame<C: PSOCipher>(mut framer: PacketFramer<C>, mut data: &[u8]) {
+    let offset = framer.header().size_offset();
+    let header_size = framer.cipher().header_size();
+    while let Some((&len, rest)) = data.split_first() {
+        let len = (len as usize).min(rest.len());
+        framer.push(&rest[..len]);
+        data = &rest[len..];
+
+        loop {
+            match framer.next_packet() {
+                Ok(Some(pkt)) => {
+                    assert!(pkt.len() >= header_size);
+                    assert!(pkt.len() == u16::from_le_bytes([pkt[offset], pkt[offset + 1]]) as usize);
+                },
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (kind, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    match kind % 4 {
+        0 => frame(PacketFramer::new(NullCipher {}), data),
+        1 => frame(PacketFramer::new(PSOPCCipher::new(0x12345678)).with_header(HeaderKind::PC), data),
+        2 => frame(PacketFramer::new(PSOGCCipher::new(0x12345678)).with_header(HeaderKind::GC), data),
+        _ => {
+            let table = BBKeyTable::from_bytes(&[0x55; BB_KEY_FILE_SIZE]).unwrap();
+            frame(PacketFramer::new(PSOBBCipher::with_key(&table, [0x33; 48])).with_header(HeaderKind::BB), data);
+        },
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use]
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use libpso::packet::login::*;
+
+fuzz_target!(|data: &[u8]| {
+    parse!(data,
+           LoginWelcome, Login, LoginResponse, RequestSettings, SendKeyAndTeamSettings,
+           RedirectClient, Checksum, ChecksumAck);
+    let _ = SendKeyAndTeamSettingsView::from_bytes(data);
+    let _ = ClientPacket::parse(data);
+    let _ = ServerPacket::parse(data);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+#[macro_use]
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use libpso::packet::patch::*;
+
+fuzz_target!(|data: &[u8]| {
+    parse!(data,
+           PatchWelcome, PatchWelcomeReply, RequestLogin, LoginReply, StartFileSend, FileSend,
+           EndFileSend, PatchStartList, ChangeDirectory, UpOneDirectory, FileInfo, PatchEndList,
+           FileInfoReply, FileInfoListEnd, FilesToPatchMetadata, FinalizePatching, Message,
+           RedirectClient);
+    let _ = FileSendView::from_bytes(data);
+    let _ = ClientPacket::parse(data);
+    let _ = ServerPacket::parse(data);
+});
@@ -0,0 +1,54 @@
+#ifndef LIBPSO_H
+#define LIBPSO_H
+
+/* generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define PSO_OK 0
+
+#define PSO_ERROR_NULL_POINTER -1
+
+#define PSO_ERROR_INVALID_SIZE -2
+
+#define PSO_ERROR_INVALID_KEY -3
+
+typedef struct PSOCipherHandle PSOCipherHandle;
+
+struct PSOCipherHandle *pso_pc_cipher_new(uint32_t seed);
+
+/**
+ * # Safety
+ * `key_table` must point to `key_table_len` readable bytes in the .nsk layout and `seed` to
+ * 48 readable bytes. returns null if either is null or the key table is too short.
+ */
+struct PSOCipherHandle *pso_bb_cipher_new(const uint8_t *key_table,
+                                          size_t key_table_len,
+                                          const uint8_t *seed);
+
+/**
+ * # Safety
+ * `cipher` must be a live handle and `data` must point to `len` writable bytes.
+ */
+int32_t pso_cipher_encrypt(struct PSOCipherHandle *cipher, uint8_t *data, size_t len);
+
+/**
+ * # Safety
+ * `cipher` must be a live handle and `data` must point to `len` writable bytes.
+ */
+int32_t pso_cipher_decrypt(struct PSOCipherHandle *cipher, uint8_t *data, size_t len);
+
+/**
+ * # Safety
+ * `cipher` must be a live handle.
+ */
+size_t pso_cipher_header_size(const struct PSOCipherHandle *cipher);
+
+/**
+ * # Safety
+ * `cipher` must be null or a handle that hasn't been freed yet.
+ */
+void pso_cipher_free(struct PSOCipherHandle *cipher);
+
+#endif /* LIBPSO_H */
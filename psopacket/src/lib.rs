@@ -3,267 +3,720 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use syn::{parse_macro_input, ItemStruct};
+use syn::{parse_macro_input, ItemStruct, Token};
+use syn::parse::{Parse, ParseStream};
 use quote::quote;
 
-#[proc_macro_attribute]
-pub fn pso_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let arg = parse_macro_input!(attr as syn::LitInt);
-    let pkt_cmd = arg.value() as u16;
 
-    let parsed = parse_macro_input!(item as ItemStruct);
+// everything besides u8 that is read with from_le_bytes
+const NUMERIC_TYPES: [&str; 9] = ["i8", "u16", "i16", "u32", "i32", "u64", "i64", "f32", "f64"];
 
-    let mut from_bytes = Vec::new();
-    let mut as_bytes = Vec::new();
-    let mut dbg_write_vars = Vec::new();
-    let mut partialeq = Vec::new();
-    
-    for f in parsed.fields.iter() {
-        if let Some(ident) = &f.ident {
-            let ident_str = ident.to_string();
+#[derive(Clone, Copy, PartialEq)]
+enum HeaderKind {
+    // u16 size, u16 cmd
+    Patch,
+    // u16 size, u8 cmd, u8 flag
+    PC,
+    // u8 cmd, u8 flag, u16 size
+    DC,
+    GC,
+    // u16 size, u16 cmd, u32 flag
+    BB,
+}
+
+impl HeaderKind {
+    fn name(self) -> &'static str {
+        match self {
+            HeaderKind::Patch => "Patch",
+            HeaderKind::PC => "PC",
+            HeaderKind::DC => "DC",
+            HeaderKind::GC => "GC",
+            HeaderKind::BB => "BB",
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            HeaderKind::BB => 8,
+            _ => 4,
+        }
+    }
+
+    fn alignment(self) -> usize {
+        match self {
+            HeaderKind::BB => 8,
+            _ => 4,
+        }
+    }
+
+    fn has_flag(self) -> bool {
+        self != HeaderKind::Patch
+    }
+}
+
+// the runtime side of HeaderKind, Direction and ServerType in libpso
+fn libpso_variant(ty: &str, variant: &str) -> proc_macro2::TokenStream {
+    let ty = syn::Ident::new(ty, proc_macro2::Span::call_site());
+    let variant = syn::Ident::new(variant, proc_macro2::Span::call_site());
+    quote!(::libpso::#ty::#variant)
+}
+
+// #[pso_packet(cmd, header = "bb", sent_by = "client", server = "login")], all but cmd optional
+struct PacketArgs {
+    cmd: u16,
+    header: HeaderKind,
+    direction: &'static str,
+    server: Option<&'static str>,
+}
 
-            match &f.ty {
-                syn::Type::Array(arr) => {
-                    let array_length = if let syn::Expr::Lit(lit) = &arr.len {
-                        if let syn::Lit::Int(int) = &lit.lit {
-                            int.value() as usize
+impl Parse for PacketArgs {
+    fn parse(input: ParseStream) -> syn::Result<PacketArgs> {
+        let cmd: syn::LitInt = input.parse()?;
+        let mut header = HeaderKind::Patch;
+        let mut direction = "Both";
+        let mut server = None;
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: syn::Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            let value: syn::LitStr = input.parse()?;
+            if key == "header" {
+                header = match value.value().as_str() {
+                    "patch" => HeaderKind::Patch,
+                    "pc" => HeaderKind::PC,
+                    "dc" => HeaderKind::DC,
+                    "gc" => HeaderKind::GC,
+                    "bb" => HeaderKind::BB,
+                    _ => return Err(syn::Error::new(value.span(), "unknown header, expected one of patch, pc, dc, gc or bb")),
+                };
+            }
+            else if key == "sent_by" {
+                direction = match value.value().as_str() {
+                    "client" => "ClientToServer",
+                    "server" => "ServerToClient",
+                    _ => return Err(syn::Error::new(value.span(), "unknown sender, expected client or server")),
+                };
+            }
+            else if key == "server" {
+                server = Some(match value.value().as_str() {
+                    "patch" => "Patch",
+                    "login" => "Login",
+                    _ => return Err(syn::Error::new(value.span(), "unknown server, expected patch or login")),
+                });
+            }
+            else {
+                return Err(syn::Error::new(key.span(), "unknown argument, expected header, sent_by or server"));
+            }
+        }
+
+        if cmd.value() > 0xFFFF || (cmd.value() > 0xFF && !matches!(header, HeaderKind::Patch | HeaderKind::BB)) {
+            return Err(syn::Error::new(cmd.span(), "command does not fit in the header"));
+        }
+
+        Ok(PacketArgs {
+            cmd: cmd.value() as u16,
+            header,
+            direction,
+            server,
+        })
+    }
+}
+
+// where a Vec field gets its length from
+enum Count {
+    // the flag in the packet header
+    Header,
+    // an earlier field in the same struct
+    Field(syn::Ident),
+    // everything up to the end of the data
+    Remaining,
+}
+
+// #[pso(count = "header.flag")], #[pso(count = "field")] or #[pso(remaining)] on Vec fields and
+// #[pso(encoding = "sjis")] on String and FixedStr fields
+#[derive(Default)]
+struct FieldAttrs {
+    count: Option<Count>,
+    encoding: Option<syn::Ident>,
+}
+
+fn is_pso_attr(attr: &syn::Attribute) -> bool {
+    attr.path.segments.len() == 1 && attr.path.segments[0].ident == "pso"
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[syn::Attribute]) -> Result<FieldAttrs, syn::Error> {
+        let mut field_attrs = FieldAttrs::default();
+        for attr in attrs.iter().filter(|attr| is_pso_attr(attr)) {
+            let list = match attr.parse_meta()? {
+                syn::Meta::List(list) => list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected #[pso(...)]")),
+            };
+            for nested in list.nested.iter() {
+                match nested {
+                    syn::NestedMeta::Meta(syn::Meta::Word(word)) if word == "remaining" => {
+                        field_attrs.count = Some(Count::Remaining);
+                    },
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.ident == "count" => {
+                        let value = match &nv.lit {
+                            syn::Lit::Str(value) => value,
+                            lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                        };
+                        field_attrs.count = Some(if value.value() == "header.flag" {
+                            Count::Header
                         }
                         else {
-                            return syn::Error::new(arr.bracket_token.span, "unknown array size").to_compile_error().into();
+                            Count::Field(value.parse()?)
+                        });
+                    },
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.ident == "encoding" => {
+                        let value = match &nv.lit {
+                            syn::Lit::Str(value) => value,
+                            lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                        };
+                        let encoding = match value.value().as_str() {
+                            "utf16" => "Utf16",
+                            "sjis" | "shift-jis" => "ShiftJis",
+                            "latin1" | "iso-8859-1" => "Latin1",
+                            "marked" => "Marked",
+                            _ => return Err(syn::Error::new_spanned(value, "unknown encoding, expected one of utf16, sjis, latin1 or marked")),
+                        };
+                        field_attrs.encoding = Some(syn::Ident::new(encoding, value.span()));
+                    },
+                    _ => return Err(syn::Error::new_spanned(nested, "unknown pso attribute")),
+                }
+            }
+        }
+        Ok(field_attrs)
+    }
+}
+
+// fields are read into locals before the struct is built so later fields can refer to earlier ones
+fn local_ident(ident: &syn::Ident) -> syn::Ident {
+    syn::Ident::new(&format!("field_{}", ident), ident.span())
+}
+
+// read and write code for a single value of a non-array type. value is a reference to write out.
+fn element_code(path: &syn::TypePath, value: proc_macro2::TokenStream) -> Result<(proc_macro2::TokenStream, proc_macro2::TokenStream), syn::Error> {
+    let ty = path.path.segments[0].ident.to_string();
+    Ok(match ty.as_str() {
+        "u8" => (quote! {
+            {
+                let mut b: [u8; 1] = [0; 1];
+                if cur.read_exact(&mut b).is_err() {
+                    return Err(PacketParseError::NotEnoughBytes);
+                }
+                b[0]
+            }
+        }, quote! {
+            buf.push(*(#value));
+        }),
+        ty if NUMERIC_TYPES.contains(&ty) => (quote! {
+            {
+                let mut b = [0u8; std::mem::size_of::<#path>()];
+                if cur.read_exact(&mut b).is_err() {
+                    return Err(PacketParseError::NotEnoughBytes);
+                }
+                #path::from_le_bytes(b)
+            }
+        }, quote! {
+            buf.extend_from_slice(&(#value).to_le_bytes());
+        }),
+        "String" | "Vec" => return Err(syn::Error::new_spanned(path, "type not supported")),
+        _ => (quote! {
+            <#path as PSOPacketData>::from_bytes(cur)?
+        }, quote! {
+            buf.append(&mut PSOPacketData::as_bytes(#value));
+        }),
+    })
+}
+
+// the generated code for a single field. read is an expression that can bail out with
+// `return Err(..)` and write appends the field to buf.
+struct FieldCode {
+    ident: syn::Ident,
+    ty: syn::Type,
+    count: Option<Count>,
+    read: proc_macro2::TokenStream,
+    write: proc_macro2::TokenStream,
+    // methods for the struct's impl block, if the field needs any
+    accessors: proc_macro2::TokenStream,
+}
+
+impl FieldCode {
+    fn new(field: &syn::Field) -> Result<FieldCode, syn::Error> {
+        let ident = field.ident.clone().ok_or_else(|| syn::Error::new_spanned(field, "fields must be named"))?;
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        let is_vec = if let syn::Type::Path(path) = &field.ty {
+            path.path.segments[0].ident == "Vec"
+        }
+        else {
+            false
+        };
+        if attrs.count.is_some() && !is_vec {
+            return Err(syn::Error::new_spanned(&field.ty, "count and remaining only apply to Vec fields"));
+        }
+        let is_string = if let syn::Type::Path(path) = &field.ty {
+            path.path.segments[0].ident == "String"
+        }
+        else {
+            false
+        };
+        let is_fixed_str = if let syn::Type::Path(path) = &field.ty {
+            path.path.segments[0].ident == "FixedStr"
+        }
+        else {
+            false
+        };
+        if attrs.encoding.is_some() && !is_string && !is_fixed_str {
+            return Err(syn::Error::new_spanned(&field.ty, "encoding only applies to String and FixedStr fields"));
+        }
+        let is_utf16 = attrs.encoding.as_ref().is_some_and(|encoding| encoding == "Utf16");
+        if is_utf16 && is_fixed_str {
+            return Err(syn::Error::new_spanned(&field.ty, "FixedStr holds 8 bit text, use FixedUtf16 for utf16"));
+        }
+        // FixedUtf16 only ever reads whole code units, strings fail the same way on half of one
+        let odd_utf16_length = quote! {
+            // half a code unit can't be padding, the packet is cut short
+            if !s.len().is_multiple_of(2) {
+                return Err(PacketParseError::NotEnoughBytes);
+            }
+        };
+
+        // a FixedStr is read and written as raw bytes either way, the encoding only gives it
+        // <field>_text and set_<field>_text to go to and from a String
+        let accessors = match &attrs.encoding {
+            Some(encoding) if is_fixed_str => {
+                let ty = &field.ty;
+                let getter = syn::Ident::new(&format!("{}_text", ident), ident.span());
+                let setter = syn::Ident::new(&format!("set_{}_text", ident), ident.span());
+                quote! {
+                    pub fn #getter(&self) -> String {
+                        self.#ident.decode(::libpso::text::Encoding::#encoding)
+                    }
+
+                    pub fn #setter(&mut self, s: &str) -> Result<(), ::libpso::text::TextError> {
+                        self.#ident = <#ty>::encode(s, ::libpso::text::Encoding::#encoding)?;
+                        Ok(())
+                    }
+                }
+            },
+            _ => quote!(),
+        };
+
+        let (read, write) = match &field.ty {
+            syn::Type::Array(arr) => {
+                let array_length = if let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. }) = &arr.len {
+                    int.value() as usize
+                }
+                else {
+                    return Err(syn::Error::new(arr.bracket_token.span, "unknown array size"));
+                };
+                let path = match &*arr.elem {
+                    syn::Type::Path(path) => path,
+                    _ => return Err(syn::Error::new_spanned(&arr.elem, "type not supported")),
+                };
+                let ty = path.path.segments[0].ident.to_string();
+                if ty == "u8" {
+                    (quote! {
+                        {
+                            let mut b: [u8; #array_length] = [0; #array_length];
+                            if cur.read_exact(&mut b).is_err() {
+                                return Err(PacketParseError::NotEnoughBytes);
+                            }
+                            b
                         }
-                    } else {
-                        return syn::Error::new(arr.bracket_token.span, "unknown array size").to_compile_error().into();
-                    };
-                    match *arr.elem {
-                        syn::Type::Path(ref path) => {
-                            let ty = path.path.segments[0].ident.to_string();
-                            if ty.as_str() == "u8_str" {
-                                dbg_write_vars.push(quote! {
-                                    match std::str::from_utf8(&self.#ident) {
-                                        Ok(v) => write!(f, "    {}: {:?}\n", #ident_str, v).unwrap(),
-                                        Err(_) => write!(f, "    {}: {:?}\n", #ident_str, self.#ident.to_vec()).unwrap()
-                                    }
-                                });
+                    }, quote! {
+                        buf.extend_from_slice(&self.#ident);
+                    })
+                }
+                else {
+                    let (read, write) = element_code(path, quote!(f))?;
+                    (quote! {
+                        {
+                            let mut v = Vec::with_capacity(#array_length);
+                            for _ in 0..#array_length {
+                                v.push(#read);
                             }
-                            else {
-                                dbg_write_vars.push(quote! {
-                                    write!(f, "    {}: {:?}\n", #ident_str, self.#ident.to_vec()).unwrap();
-                                });
+                            <[#path; #array_length] as std::convert::TryFrom<Vec<#path>>>::try_from(v)
+                                .map_err(|_| PacketParseError::NotEnoughBytes)?
+                        }
+                    }, quote! {
+                        for f in self.#ident.iter() {
+                            #write
+                        }
+                    })
+                }
+            },
+            syn::Type::Path(path) if is_vec => {
+                let elem = match &path.path.segments[0].arguments {
+                    syn::PathArguments::AngleBracketed(args) => match args.args.first().map(|arg| arg.into_value()) {
+                        Some(syn::GenericArgument::Type(syn::Type::Path(elem))) => elem,
+                        _ => return Err(syn::Error::new_spanned(path, "type not supported")),
+                    },
+                    _ => return Err(syn::Error::new_spanned(path, "type not supported")),
+                };
+                let (read, write) = element_code(elem, quote!(f))?;
+                let read = match &attrs.count {
+                    Some(Count::Header) => quote! {
+                        {
+                            let mut v = Vec::new();
+                            for _ in 0..flag as usize {
+                                v.push(#read);
                             }
-                            //dbg_write_vars.push(quote! {
-                                //write!(f, "    {}: {:?}\n", #ident_str, self.#ident.iter()).unwrap();
-                                /*match std::str::from_utf8(&self.#ident) {
-                                    Ok(v) => write!(f, "    {}: {:?}\n", #ident_str, v).unwrap(),
-                                    Err(_) => write!(f, "    {}: {:?}\n", #ident_str, self.#ident.iter()).unwrap()
-                                }*/
-                                //write!(f, "    {}: {:?}\n", #ident_str, var_as_str).unwrap();
-                            //});
-                            as_bytes.push(quote! {
-                                for f in self.#ident.iter() {
-                                    buf.extend_from_slice(&f.to_le_bytes())
-                                }
-                            });
-                            match ty.as_str() {
-                                "u8" | "u8_str" => {
-                                    from_bytes.push(quote! {
-                                        #ident: {
-                                            let mut b: [u8; #array_length] = [0; #array_length];
-                                            if let Ok(len) = cur.read(&mut b) {
-                                                if len != #array_length {
-                                                    return Err(PacketParseError::NotEnoughBytes);
-                                                }
-                                            }
-                                            else {
-                                                return Err(PacketParseError::NotEnoughBytes);
-                                            };
-                                            b
-                                        },
-                                    });
-                                },
-                                _ => {
-                                    return syn::Error::new(path.path.segments[0].ident.span(), "type not supported")
-                                        .to_compile_error().into();
+                            v
+                        }
+                    },
+                    Some(Count::Field(count)) => {
+                        let count = local_ident(count);
+                        quote! {
+                            {
+                                let mut v = Vec::new();
+                                for _ in 0..#count as usize {
+                                    v.push(#read);
                                 }
+                                v
                             }
-                            partialeq.push(quote! {
-                                if self.#ident[..] != other.#ident[..] {
-                                    return false;
-                                }
-                            });
                         }
-                        _ => {
-                            panic!("why");
-                        }
-                    }
-                },
-                syn::Type::Path(path) => {
-                    dbg_write_vars.push(quote! {
-                        write!(f, "    {}: {:?}\n", #ident_str, self.#ident).unwrap();
-                    });
-                    let ty = path.path.segments[0].ident.to_string();
-
-                    // as_bytes
-                    match ty.as_str() {
-                        "String" => {
-                            as_bytes.push(quote! {
-                                for c in self.#ident.as_str().encode_utf16() {
-                                    buf.extend_from_slice(&c.to_le_bytes());
+                    },
+                    Some(Count::Remaining) => quote! {
+                        {
+                            let mut v = Vec::new();
+                            while (cur.position() as usize) < cur.get_ref().len() {
+                                let start = cur.position();
+                                #[allow(clippy::redundant_closure_call, clippy::needless_question_mark)]
+                                let value: Result<_, PacketParseError> = (|| Ok(#read))();
+                                match value {
+                                    Ok(value) => v.push(value),
+                                    // a partial entry of zeros at the end is the packet's padding
+                                    Err(_) if cur.get_ref()[start as usize..].iter().all(|b| *b == 0) => {
+                                        cur.set_position(start);
+                                        break;
+                                    },
+                                    Err(err) => return Err(err),
                                 }
-                                //buf.extend_from_slice(&self.#ident.as_str().encode_utf16());
-                            });
-                        }
-                        _ => {
-                            as_bytes.push(quote! {
-                                buf.extend_from_slice(&self.#ident.to_le_bytes());
-                            });
+                            }
+                            v
                         }
+                    },
+                    None => return Err(syn::Error::new_spanned(&field.ty, "Vec fields need #[pso(count = \"...\")] or #[pso(remaining)]")),
+                };
+                (read, quote! {
+                    for f in self.#ident.iter() {
+                        #write
                     }
-
-                    // from_bytes
-                    match ty.as_str() {
-                        "u8" | "u8_str" => {
-                            from_bytes.push(quote! {
-                                #ident: {
-                                    let mut b: [u8; 1] = [0; 1];
-                                    if let Ok(len) = cur.read(&mut b) {
-                                        if len != 1 {
-                                            return Err(PacketParseError::NotEnoughBytes);
-                                        }
-                                    }
-                                    else {
-                                        return Err(PacketParseError::NotEnoughBytes);
-                                    };
-                                    b[0]
-                                },
-                            });
-                        },
-                        "u16" => {
-                            from_bytes.push(quote! {
-                                #ident: {
-                                    let mut b: [u8; 2] = [0; 2];
-                                    if let Ok(len) = cur.read(&mut b) {
-                                        if len != 2 {
-                                            return Err(PacketParseError::NotEnoughBytes);
-                                        }
-                                    }
-                                    else {
-                                        return Err(PacketParseError::NotEnoughBytes);
-                                    };
-                                    u16::from_le_bytes(b)
-                                },
-                            });
-                        },
-                        "u32" => {
-                            from_bytes.push(quote! {
-                                #ident: {
-                                    let mut b: [u8; 4] = [0; 4];
-                                    if let Ok(len) = cur.read(&mut b) {
-                                        if len != 4 {
-                                            return Err(PacketParseError::NotEnoughBytes);
-                                        }
-                                    }
-                                    else {
-                                        return Err(PacketParseError::NotEnoughBytes);
-                                    };
-                                    u32::from_le_bytes(b)
-                                },
-                            });
-                        },
-                        "String" => {
-                            from_bytes.push(quote! {
-                                #ident: {
-                                    let mut s: Vec<u8> = Vec::new();
-                                    if let Ok(len) = cur.read_to_end(&mut s) {
-                                    }
-                                    else {
-                                        return Err(PacketParseError::NotEnoughBytes);
-                                    };
-                                    let mut utf16 = Vec::new();
-                                    for c in s.chunks(2) {
-                                        utf16.push(u16::from_le_bytes([c[0], c[1]]));
-                                    }
-                                    String::from_utf16_lossy(utf16.as_slice())
-                                },
-                            });
-                        },
-                        _ => {
-                            from_bytes.push(quote! {
-                                #ident: {
-                                    let mut b: [u8; #path::SIZE] = [0; #path::SIZE];
-                                    if let Ok(len) = cur.read(&mut b) {
-                                        if len != #path::SIZE {
-                                            return Err(PacketParseError::NotEnoughBytes);
-                                        }
-                                    }
-                                    else {
-                                        return Err(PacketParseError::NotEnoughBytes);
-                                    };
-                                    #path::from_le_bytes(b)?
-                                },
-                            });
-                            //return syn::Error::new(path.path.segments[0].ident.span(), "type not supported")
-                            //    .to_compile_error().into();
+                })
+            },
+            syn::Type::Path(_) if is_string && attrs.encoding.is_some() => {
+                let encoding = &attrs.encoding;
+                let odd_length = if is_utf16 {
+                    odd_utf16_length.clone()
+                }
+                else {
+                    quote!()
+                };
+                (quote! {
+                    {
+                        let mut s: Vec<u8> = Vec::new();
+                        if cur.read_to_end(&mut s).is_err() {
+                            return Err(PacketParseError::NotEnoughBytes);
                         }
+                        #odd_length
+                        ::libpso::text::Encoding::#encoding.decode(&s)
                     }
-                    partialeq.push(quote! {
-                        if self.#ident != other.#ident {
-                            return false;
-                        }
-                    });
+                }, quote! {
+                    buf.append(&mut ::libpso::text::Encoding::#encoding.encode_lossy(&self.#ident));
+                })
+            },
+            syn::Type::Path(_) if is_string => (quote! {
+                {
+                    let mut s: Vec<u8> = Vec::new();
+                    if cur.read_to_end(&mut s).is_err() {
+                        return Err(PacketParseError::NotEnoughBytes);
+                    }
+                    #odd_utf16_length
+                    let utf16: Vec<u16> = s.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                    String::from_utf16_lossy(utf16.as_slice())
                 }
-                _ => {
+            }, quote! {
+                for c in self.#ident.as_str().encode_utf16() {
+                    buf.extend_from_slice(&c.to_le_bytes());
                 }
-            }
+            }),
+            syn::Type::Path(path) => element_code(path, quote!(&self.#ident))?,
+            _ => return Err(syn::Error::new_spanned(&field.ty, "type not supported")),
+        };
+
+        Ok(FieldCode {
+            ident,
+            ty: field.ty.clone(),
+            count: attrs.count,
+            read,
+            write,
+            accessors,
+        })
+    }
+}
+
+// builds the code for every field of a struct and points count fields at the Vec they count, so
+// the length is written back from the Vec. also returns the Vec counted by the header flag, if any.
+fn struct_fields<'a, I: Iterator<Item = &'a syn::Field>>(fields: I, header_flag: bool) -> Result<(Vec<FieldCode>, Option<syn::Ident>), syn::Error> {
+    let mut code = fields.map(FieldCode::new).collect::<Result<Vec<_>, _>>()?;
+    let mut header_count = None;
+
+    for i in 0..code.len() {
+        let vec_ident = code[i].ident.clone();
+        match &code[i].count {
+            Some(Count::Header) => {
+                if !header_flag {
+                    return Err(syn::Error::new_spanned(&code[i].ident, "no header flag to count with"));
+                }
+                header_count = Some(vec_ident);
+            },
+            Some(Count::Field(count)) => {
+                let count = count.clone();
+                let counter = match code[..i].iter_mut().find(|f| f.ident == count) {
+                    Some(counter) => counter,
+                    None => return Err(syn::Error::new_spanned(&count, "count has to be an earlier field")),
+                };
+                let ty = &counter.ty;
+                let message = format!("{{}} entries in {} do not fit the {} count field {}", vec_ident, quote!(#ty), count);
+                counter.write = quote! {
+                    {
+                        let len = self.#vec_ident.len();
+                        let count = <#ty as std::convert::TryFrom<usize>>::try_from(len)
+                            .unwrap_or_else(|_| panic!(#message, len));
+                        buf.extend_from_slice(&count.to_le_bytes());
+                    }
+                };
+            },
+            _ => {},
         }
     }
 
+    Ok((code, header_count))
+}
+
+// each field is read in its own closure so a failure can be tagged with where it happened.
+// returns the statements reading every field into a local and the struct field initializers.
+fn read_fields(struct_name: &str, fields: &[FieldCode]) -> (Vec<proc_macro2::TokenStream>, Vec<proc_macro2::TokenStream>) {
+    fields.iter().map(|field| {
+        let ident = &field.ident;
+        let local = local_ident(ident);
+        let ident_str = ident.to_string();
+        let read = &field.read;
+        (quote! {
+            let #local = {
+                let offset = cur.position();
+                #[allow(clippy::redundant_closure_call, clippy::needless_question_mark)]
+                let value: Result<_, PacketParseError> = (|| Ok(#read))();
+                value.map_err(|err| PacketParseError::Field {
+                    packet: #struct_name,
+                    field: #ident_str,
+                    offset,
+                    error: Box::new(err),
+                })?
+            };
+        }, quote! {
+            #ident: #local,
+        })
+    }).unzip()
+}
+
+#[proc_macro_attribute]
+pub fn pso_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as PacketArgs);
+    let pkt_cmd = args.cmd;
+    let header = args.header;
+
+    let mut parsed = parse_macro_input!(item as ItemStruct);
+
+    // lives in the header rather than the body
+    let flag_field = header.has_flag() && parsed.fields.iter().any(|f| f.ident.as_ref().map(|i| i == "flag").unwrap_or(false));
+    let fields = parsed.fields.iter()
+        .filter(|f| !(flag_field && f.ident.as_ref().map(|i| i == "flag").unwrap_or(false)));
+
     let this_struct = parsed.ident.clone();
     let this_struct_str = this_struct.to_string();
 
+    let (field_code, header_count) = match struct_fields(fields, header.has_flag()) {
+        Ok(code) => code,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    if flag_field && header_count.is_some() {
+        return syn::Error::new_spanned(&parsed.ident, "a flag field can't be used with count = \"header.flag\"").to_compile_error().into();
+    }
+    let (from_bytes, field_inits) = read_fields(&this_struct_str, &field_code);
+    let as_bytes = field_code.iter().map(|f| &f.write).collect::<Vec<_>>();
+
+    for f in parsed.fields.iter_mut() {
+        f.attrs.retain(|attr| !is_pso_attr(attr));
+    }
+
+    let mut dbg_write_vars = Vec::new();
+    let mut partialeq = Vec::new();
+    for f in parsed.fields.iter() {
+        if let Some(ident) = &f.ident {
+            let ident_str = ident.to_string();
+            if let syn::Type::Array(_) = f.ty {
+                dbg_write_vars.push(quote! {
+                    write!(f, "    {}: {:?}\n", #ident_str, &self.#ident[..]).unwrap();
+                });
+                partialeq.push(quote! {
+                    if self.#ident[..] != other.#ident[..] {
+                        return false;
+                    }
+                });
+            }
+            else {
+                dbg_write_vars.push(quote! {
+                    write!(f, "    {}: {:?}\n", #ident_str, self.#ident).unwrap();
+                });
+                partialeq.push(quote! {
+                    if self.#ident != other.#ident {
+                        return false;
+                    }
+                });
+            }
+        }
+    }
+
+    let header_size = header.size();
+    let alignment = header.alignment();
+
+    let read_header = match header {
+        HeaderKind::Patch => quote! {
+            (u16::from_le_bytes([header[0], header[1]]), u16::from_le_bytes([header[2], header[3]]), 0u32)
+        },
+        HeaderKind::PC => quote! {
+            (u16::from_le_bytes([header[0], header[1]]), header[2] as u16, header[3] as u32)
+        },
+        HeaderKind::DC | HeaderKind::GC => quote! {
+            (u16::from_le_bytes([header[2], header[3]]), header[0] as u16, header[1] as u32)
+        },
+        HeaderKind::BB => quote! {
+            (u16::from_le_bytes([header[0], header[1]]), u16::from_le_bytes([header[2], header[3]]),
+             u32::from_le_bytes([header[4], header[5], header[6], header[7]]))
+        },
+    };
+
+    // pc, dc and gc only have a byte for the flag
+    let flag_byte = quote! {
+        <u8 as std::convert::TryFrom<u32>>::try_from(flag)
+            .unwrap_or_else(|_| panic!("{} flag {} does not fit the u8 flag field", #this_struct_str, flag))
+    };
+
+    // written over the placeholder once the body and its length are known
+    let write_header = match header {
+        HeaderKind::Patch => quote! {
+            header[0..2].copy_from_slice(&u16::to_le_bytes(pkt_len));
+            header[2..4].copy_from_slice(&u16::to_le_bytes(#pkt_cmd));
+        },
+        HeaderKind::PC => quote! {
+            header[0..2].copy_from_slice(&u16::to_le_bytes(pkt_len));
+            header[2] = #pkt_cmd as u8;
+            header[3] = #flag_byte;
+        },
+        HeaderKind::DC | HeaderKind::GC => quote! {
+            header[0] = #pkt_cmd as u8;
+            header[1] = #flag_byte;
+            header[2..4].copy_from_slice(&u16::to_le_bytes(pkt_len));
+        },
+        HeaderKind::BB => quote! {
+            header[0..2].copy_from_slice(&u16::to_le_bytes(pkt_len));
+            header[2..4].copy_from_slice(&u16::to_le_bytes(#pkt_cmd));
+            header[4..8].copy_from_slice(&u32::to_le_bytes(flag));
+        },
+    };
+
+    let (read_flag, write_flag) = if flag_field {
+        (quote!(flag: flag as _,), quote! {
+            let flag = <u32 as std::convert::TryFrom<_>>::try_from(self.flag)
+                .unwrap_or_else(|_| panic!("{} flag {} does not fit the u32 flag field", #this_struct_str, self.flag));
+        })
+    }
+    else if let Some(count) = &header_count {
+        let message = format!("{{}} entries in {} do not fit the u32 flag field", count);
+        (quote!(), quote! {
+            let len = self.#count.len();
+            let flag = <u32 as std::convert::TryFrom<usize>>::try_from(len)
+                .unwrap_or_else(|_| panic!(#message, len));
+        })
+    }
+    else {
+        (quote!(), quote!(let flag = 0u32;))
+    };
+    let unused_flag = if flag_field || header_count.is_some() {
+        quote!()
+    }
+    else {
+        quote!(let _ = flag;)
+    };
+
     let psopacket = quote! {
         impl PSOPacket for #this_struct {
-            fn from_bytes(data: &Vec<u8>) -> Result<#this_struct, PacketParseError> {
-                let mut cur = std::io::Cursor::new(data);
-                let mut b: [u8; 2] = [0; 2];
-                cur.read(&mut b).unwrap();
-                let len = u16::from_le_bytes(b);
-                cur.read(&mut b).unwrap();
-                let cmd = u16::from_le_bytes(b);
+            fn from_bytes(data: &[u8]) -> Result<#this_struct, PacketParseError> {
+                let cur = &mut std::io::Cursor::new(data);
+                let mut header = [0u8; #header_size];
+                if cur.read_exact(&mut header).is_err() {
+                    return Err(PacketParseError::NotEnoughBytes);
+                }
+                let (len, cmd, flag) = #read_header;
+                #unused_flag
 
                 if cmd != #pkt_cmd {
-                    return Err(PacketParseError::WrongPacketCommand);
+                    return Err(PacketParseError::WrongPacketCommand {
+                        packet: #this_struct_str,
+                        expected: #pkt_cmd,
+                        got: cmd,
+                    });
                 }
 
                 if len as usize != data.len() {
-                    return Err(PacketParseError::WrongPacketSize(len, data.len()));
+                    return Err(PacketParseError::WrongPacketSize {
+                        packet: #this_struct_str,
+                        cmd,
+                        declared: len,
+                        len: data.len(),
+                    });
                 }
                 
-                let result = Ok(#this_struct {
-                    #(#from_bytes)*
-                });
+                #(#from_bytes)*
 
-                if cur.position() as usize != data.len() {
-                    return Err(PacketParseError::DataStructNotLargeEnough(cur.position(), data.len()));
+                // whatever is left has to be padding out to the alignment
+                let position = cur.position() as usize;
+                if position != data.len() && position.div_ceil(#alignment) * #alignment != data.len() {
+                    return Err(PacketParseError::DataStructNotLargeEnough {
+                        packet: #this_struct_str,
+                        cmd,
+                        position: cur.position(),
+                        len: data.len(),
+                    });
                 }
 
-                result
+                Ok(#this_struct {
+                    #read_flag
+                    #(#field_inits)*
+                })
             }
-            fn as_bytes(&self) -> Vec<u8> {
-                let mut buf: Vec<u8> = Vec::new();
+            fn encode_into(&self, buf: &mut Vec<u8>) {
+                let start = buf.len();
+                buf.resize(start + #header_size, 0);
                 #(#as_bytes)*
 
-                while buf.len() % 4 != 0 {
+                while !(buf.len() - start).is_multiple_of(#alignment) {
                     buf.push(0);
                 }
 
-                let pkt_len = (buf.len() + 4) as u16;
-                let mut prebuf: Vec<u8> = Vec::new();
-
-                prebuf.extend_from_slice(&u16::to_le_bytes(pkt_len));
-                prebuf.extend_from_slice(&u16::to_le_bytes(#pkt_cmd));
-                prebuf.append(&mut buf);
-
-                prebuf
+                let pkt_len = <u16 as std::convert::TryFrom<usize>>::try_from(buf.len() - start)
+                    .unwrap_or_else(|_| panic!("{} byte {} does not fit the u16 size field", buf.len() - start, #this_struct_str));
+                #write_flag
+                let header = &mut buf[start..start + #header_size];
+                #write_header
             }
         }
     };
@@ -289,12 +742,31 @@ pub fn pso_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     };
 
+    let header_kind = libpso_variant("HeaderKind", header.name());
+    let direction = libpso_variant("Direction", args.direction);
+    let server = match args.server {
+        Some(server) => {
+            let server = libpso_variant("ServerType", server);
+            quote!(Some(#server))
+        },
+        None => quote!(None),
+    };
+    let accessors = field_code.iter().map(|f| &f.accessors).collect::<Vec<_>>();
+    let psopacket_info = quote! {
+        impl #this_struct {
+            #(#accessors)*
+        }
+
+        ::libpso::packet_info!(#this_struct, #pkt_cmd, #header_kind, #direction, #server);
+    };
+
     let q = quote! {
         #[derive(Clone)]
         #parsed
         #psopacket
         #psopacket_debug
         #psopacket_partialeq
+        #psopacket_info
     };
 
     //println!("[[[{}]]]", q.to_string());
@@ -303,7 +775,170 @@ pub fn pso_packet(attr: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 
+// implements PSOPacketData for a plain struct so it can be embedded in a #[pso_packet], on its
+// own or in a fixed size array. fields are laid out back to back with no padding.
+#[proc_macro_derive(PSOPacketData, attributes(pso))]
+pub fn pso_packet_data(item: TokenStream) -> TokenStream {
+    let parsed = parse_macro_input!(item as ItemStruct);
+    let this_struct = parsed.ident.clone();
+    let this_struct_str = this_struct.to_string();
+
+    let (field_code, _) = match struct_fields(parsed.fields.iter(), false) {
+        Ok(code) => code,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (from_bytes, field_inits) = read_fields(&this_struct_str, &field_code);
+    let as_bytes = field_code.iter().map(|f| &f.write).collect::<Vec<_>>();
+    let accessors = field_code.iter().map(|f| &f.accessors).collect::<Vec<_>>();
+
+    let q = quote! {
+        impl #this_struct {
+            #(#accessors)*
+        }
+
+        impl PSOPacketData for #this_struct {
+            fn from_bytes(cur: &mut std::io::Cursor<&[u8]>) -> Result<#this_struct, PacketParseError> {
+                #(#from_bytes)*
+                Ok(#this_struct {
+                    #(#field_inits)*
+                })
+            }
+
+            fn as_bytes(&self) -> Vec<u8> {
+                let mut buf: Vec<u8> = Vec::new();
+                #(#as_bytes)*
+                buf
+            }
+        }
+    };
+
+    q.into()
+}
+
+
+// implements PSOPacketData for a fieldless enum stored as its #[repr] integer. discriminants work
+// as they do in rust. a variant marked #[pso(unknown)] holding the raw value as the repr type
+// catches anything unrecognised, without one those fail to parse with InvalidValue.
+#[proc_macro_derive(PSOEnum, attributes(pso))]
+pub fn pso_enum(item: TokenStream) -> TokenStream {
+    let parsed = parse_macro_input!(item as syn::ItemEnum);
+    match enum_code(&parsed) {
+        Ok(q) => q.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn enum_code(parsed: &syn::ItemEnum) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let this_enum = &parsed.ident;
+
+    let mut repr = None;
+    for attr in parsed.attrs.iter().filter(|attr| attr.path.segments.len() == 1 && attr.path.segments[0].ident == "repr") {
+        if let syn::Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested.iter() {
+                if let syn::NestedMeta::Meta(syn::Meta::Word(word)) = nested {
+                    if word == "u8" || word == "u16" || word == "u32" {
+                        repr = Some(word.clone());
+                    }
+                }
+            }
+        }
+    }
+    let repr = repr.ok_or_else(|| syn::Error::new_spanned(this_enum, "PSOEnum needs #[repr(u8)], #[repr(u16)] or #[repr(u32)]"))?;
+    let max_value = match repr.to_string().as_str() {
+        "u8" => 0xFF,
+        "u16" => 0xFFFF,
+        _ => 0xFFFF_FFFF,
+    };
+
+    let mut from_bytes = Vec::new();
+    let mut as_bytes = Vec::new();
+    let mut unknown = None;
+    let mut next_value = 0u64;
+    for variant in parsed.variants.iter() {
+        let ident = &variant.ident;
+        let is_unknown = variant.attrs.iter()
+            .filter(|attr| is_pso_attr(attr))
+            .any(|attr| match attr.parse_meta() {
+                Ok(syn::Meta::List(list)) => list.nested.iter().any(|nested| match nested {
+                    syn::NestedMeta::Meta(syn::Meta::Word(word)) => word == "unknown",
+                    _ => false,
+                }),
+                _ => false,
+            });
+
+        if is_unknown {
+            match &variant.fields {
+                syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 && unknown.is_none() => {
+                    // holding anything else would let encoding truncate the value
+                    let ty = &fields.unnamed[0].ty;
+                    match ty {
+                        syn::Type::Path(path) if path.qself.is_none() && path.path.is_ident(repr.clone()) => {},
+                        _ => return Err(syn::Error::new_spanned(ty, format!("the unknown variant has to hold the repr type, {}", repr))),
+                    }
+                    unknown = Some(ident.clone());
+                },
+                _ => return Err(syn::Error::new_spanned(variant, format!("expected a single variant like Unknown({})", repr))),
+            }
+            continue;
+        }
+        if variant.fields != syn::Fields::Unit {
+            return Err(syn::Error::new_spanned(variant, "only unit variants and one #[pso(unknown)] variant are supported"));
+        }
+
+        let value = match &variant.discriminant {
+            Some((_, syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. }))) => int.value(),
+            Some((_, expr)) => return Err(syn::Error::new_spanned(expr, "discriminants have to be integer literals")),
+            None => next_value,
+        };
+        if value > max_value {
+            return Err(syn::Error::new_spanned(variant, "discriminant does not fit in the repr"));
+        }
+        next_value = value + 1;
+
+        let value = proc_macro2::Literal::u64_unsuffixed(value);
+        from_bytes.push(quote! {
+            #value => Ok(#this_enum::#ident),
+        });
+        as_bytes.push(quote! {
+            #this_enum::#ident => #value,
+        });
+    }
+
+    let unknown_value = match &unknown {
+        Some(ident) => {
+            as_bytes.push(quote! {
+                #this_enum::#ident(value) => *value,
+            });
+            quote!(value => Ok(#this_enum::#ident(value)),)
+        },
+        None => quote!(_ => Err(PacketParseError::InvalidValue),),
+    };
+
+    Ok(quote! {
+        impl PSOPacketData for #this_enum {
+            fn from_bytes(cur: &mut std::io::Cursor<&[u8]>) -> Result<#this_enum, PacketParseError> {
+                let mut b = [0u8; std::mem::size_of::<#repr>()];
+                if cur.read_exact(&mut b).is_err() {
+                    return Err(PacketParseError::NotEnoughBytes);
+                }
+                match #repr::from_le_bytes(b) {
+                    #(#from_bytes)*
+                    #unknown_value
+                }
+            }
+
+            fn as_bytes(&self) -> Vec<u8> {
+                let value: #repr = match self {
+                    #(#as_bytes)*
+                };
+                value.to_le_bytes().to_vec()
+            }
+        }
+    })
+}
+
+
 #[proc_macro_attribute]
-pub fn game_command(attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn game_command(_attr: TokenStream, item: TokenStream) -> TokenStream {
     item
 }
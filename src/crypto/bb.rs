@@ -1,23 +1,118 @@
+use crate::HeaderKind;
 use crate::crypto::{PSOCipher, CipherError};
-use std::num::Wrapping as W;
+use std::path::Path;
 
 const BB_SBOX_COUNT: usize = 4;
 const BB_SBOX_SIZE: usize = 256;
 const BB_PARRAY_SIZE: usize = 18;
 const BB_SEED_SIZE: usize = 48;
+pub const BB_BLOCK_SIZE: usize = 8;
+pub const BB_KEY_FILE_SIZE: usize = (BB_PARRAY_SIZE + BB_SBOX_COUNT * BB_SBOX_SIZE) * 4;
 
+
+// crypto::pad_packet for bb packets
+pub fn pad_packet(packet: &mut Vec<u8>) -> Result<(), CipherError> {
+    crate::crypto::pad_packet(packet, HeaderKind::BB, BB_BLOCK_SIZE)
+}
+
+
+// the initial p-array and s-boxes a client build was compiled with
+#[derive(Clone)]
+pub struct BBKeyTable {
+    pub p_array: [u32; BB_PARRAY_SIZE],
+    pub sbox: [[u32; BB_SBOX_SIZE]; BB_SBOX_COUNT],
+}
+
+impl BBKeyTable {
+    // newserv-style .nsk layout: the p-array followed by the s-boxes as little endian u32s.
+    // newserv appends a subtype marker after the tables, anything past them is ignored.
+    pub fn from_bytes(data: &[u8]) -> Result<BBKeyTable, CipherError> {
+        if data.len() < BB_KEY_FILE_SIZE {
+            return Err(CipherError::InvalidKeyFile(data.len()));
+        }
+
+        let mut words = data[..BB_KEY_FILE_SIZE].chunks(4).map(|k| {
+            u32::from_le_bytes([k[0], k[1], k[2], k[3]])
+        });
+
+        let mut key = BBKeyTable {
+            p_array: [0; BB_PARRAY_SIZE],
+            sbox: [[0; BB_SBOX_SIZE]; BB_SBOX_COUNT],
+        };
+        for (dst, src) in key.p_array.iter_mut().zip(&mut words) {
+            *dst = src;
+        }
+        for (dst, src) in key.sbox.iter_mut().flat_map(|s| s.iter_mut()).zip(&mut words) {
+            *dst = src;
+        }
+
+        Ok(key)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BBKeyTable, CipherError> {
+        let data = std::fs::read(path).map_err(CipherError::Io)?;
+        BBKeyTable::from_bytes(&data)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.p_array.iter()
+            .chain(self.sbox.iter().flat_map(|s| s.iter()))
+            .flat_map(|k| k.to_le_bytes().to_vec())
+            .collect()
+    }
+}
+
+// only works on whole 8 byte blocks and never pads on its own, anything else is InvalidSize
+#[derive(Clone)]
 pub struct PSOBBCipher {
     p_array: [u32; BB_PARRAY_SIZE],
     sbox: [[u32; BB_SBOX_SIZE]; BB_SBOX_COUNT],
 }
 
 impl PSOBBCipher {
+    // indexing a 256 entry s-box with a u8 can't go out of bounds, so the compiler drops the checks
+    #[inline(always)]
     fn f(&self, x: u32) -> u32 {
-        let mut k = W(self.sbox[0][((x >> 24) & 0xFF) as usize]);
-        k += W(self.sbox[1][((x >> 16) & 0xFF) as usize]);
-        k ^= W(self.sbox[2][((x >> 8) & 0xFF) as usize]);
-        k += W(self.sbox[3][((x) & 0xFF) as usize]);
-        k.0
+        let a = self.sbox[0][(x >> 24) as u8 as usize];
+        let b = self.sbox[1][(x >> 16) as u8 as usize];
+        let c = self.sbox[2][(x >> 8) as u8 as usize];
+        let d = self.sbox[3][x as u8 as usize];
+        (a.wrapping_add(b) ^ c).wrapping_add(d)
+    }
+
+    // bb only runs 4 of the 16 blowfish rounds once the key is set up
+    #[inline(always)]
+    fn encrypt_block(&self, mut l: u32, mut r: u32) -> (u32, u32) {
+        l ^= self.p_array[0];
+        r ^= self.f(l) ^ self.p_array[1];
+        l ^= self.f(r) ^ self.p_array[2];
+        r ^= self.f(l) ^ self.p_array[3];
+        l ^= self.f(r);
+        (r ^ self.p_array[5], l ^ self.p_array[4])
+    }
+
+    #[inline(always)]
+    fn decrypt_block(&self, mut l: u32, mut r: u32) -> (u32, u32) {
+        l ^= self.p_array[5];
+        r ^= self.f(l) ^ self.p_array[4];
+        l ^= self.f(r) ^ self.p_array[3];
+        r ^= self.f(l) ^ self.p_array[2];
+        l ^= self.f(r);
+        (r ^ self.p_array[0], l ^ self.p_array[1])
+    }
+
+    fn crypt_blocks(&self, data: &mut [u8], block: fn(&PSOBBCipher, u32, u32) -> (u32, u32)) -> Result<(), CipherError> {
+        if !data.len().is_multiple_of(BB_BLOCK_SIZE) {
+            return Err(CipherError::InvalidSize { block_size: BB_BLOCK_SIZE, len: data.len() });
+        }
+
+        for d in data.chunks_exact_mut(BB_BLOCK_SIZE) {
+            let (l, r) = block(self, u32::from_le_bytes([d[0], d[1], d[2], d[3]]), u32::from_le_bytes([d[4], d[5], d[6], d[7]]));
+            d[..4].copy_from_slice(&l.to_le_bytes());
+            d[4..].copy_from_slice(&r.to_le_bytes());
+        }
+
+        Ok(())
     }
 
     fn init_block(&self, mut l: u32, mut r: u32) -> (u32, u32) {
@@ -48,7 +143,7 @@ impl PSOBBCipher {
         for k in cipher.p_array.iter_mut() {
             let mut pt = *k as u16;
             pt = ((pt & 0x00FF) << 8) + ((pt & 0xFF00) >> 8);
-            *k = ((((*k >> 16) ^ pt as u32) << 16)) + pt as u32;
+            *k = (((*k >> 16) ^ pt as u32) << 16) + pt as u32;
         }
 
         for i in 0..18 {
@@ -82,72 +177,61 @@ impl PSOBBCipher {
 
         cipher
     }
-}
 
-impl PSOCipher for PSOBBCipher {
-    fn encrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        let mut real_data = data.chunks(4).map(|k| {
-            u32::from_le_bytes([k[0], k[1], k[2], k[3]])
-        }).collect::<Vec<_>>();
-        if real_data.len() % 2 == 1 {
-            real_data.push(0);
-        }
-
-        let mut result = Vec::new();
-        for d in real_data.chunks(2) {
-            let mut l = d[0];
-            let mut r = d[1];
-            for i in (0..4).step_by(2) {
-                l ^= self.p_array[i];
-                r ^= self.f(l);
-                r ^= self.p_array[i + 1];
-                l ^= self.f(r);
-            }
-            l ^= self.p_array[4];
-            r ^= self.p_array[5];
+    pub fn with_key(key: &BBKeyTable, seed: [u8; BB_SEED_SIZE]) -> PSOBBCipher {
+        PSOBBCipher::new(key.p_array, key.sbox, seed)
+    }
 
-            let tmp = l;
-            l = r;
-            r = tmp;
+    // the keyed p-array and s-boxes, laid out the same way as a key file
+    pub fn as_bytes(&self) -> Vec<u8> {
+        BBKeyTable {
+            p_array: self.p_array,
+            sbox: self.sbox,
+        }.as_bytes()
+    }
 
-            result.extend_from_slice(&l.to_le_bytes());
-            result.extend_from_slice(&r.to_le_bytes());
+    pub fn from_bytes(data: &[u8]) -> Result<PSOBBCipher, CipherError> {
+        if data.len() != BB_KEY_FILE_SIZE {
+            return Err(CipherError::InvalidState);
         }
 
-        Ok(result)
+        let state = BBKeyTable::from_bytes(data)?;
+        Ok(PSOBBCipher {
+            p_array: state.p_array,
+            sbox: state.sbox,
+        })
     }
 
-    fn decrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        if data.len() % 8 != 0 {
-            return Err(CipherError::InvalidSize);
+    // the bb cipher keeps no stream position, so a set of packets can be encrypted in one go and
+    // from a shared reference. every packet is checked before any of them are touched.
+    pub fn encrypt_batch(&self, packets: &mut [&mut [u8]]) -> Result<(), CipherError> {
+        if let Some(pkt) = packets.iter().find(|pkt| !pkt.len().is_multiple_of(BB_BLOCK_SIZE)) {
+            return Err(CipherError::InvalidSize { block_size: BB_BLOCK_SIZE, len: pkt.len() });
         }
+        for pkt in packets.iter_mut() {
+            self.crypt_blocks(pkt, PSOBBCipher::encrypt_block)?;
+        }
+        Ok(())
+    }
 
-        let real_data = data.chunks(4).map(|k| {
-            u32::from_le_bytes([k[0], k[1], k[2], k[3]])
-        }).collect::<Vec<_>>();
-
-        let mut result = Vec::new();
-        for d in real_data.chunks(2) {
-            let mut l = d[0];
-            let mut r = d[1];
-            for i in (1..=4).rev().step_by(2) {
-                l ^= self.p_array[i + 1];
-                r ^= self.f(l);
-                r ^= self.p_array[i];
-                l ^= self.f(r);
-            }
-            l ^= self.p_array[1];
-            r ^= self.p_array[0];
-
-            let tmp = l;
-            l = r;
-            r = tmp;
-
-            result.extend_from_slice(&l.to_le_bytes());
-            result.extend_from_slice(&r.to_le_bytes());
+    pub fn decrypt_batch(&self, packets: &mut [&mut [u8]]) -> Result<(), CipherError> {
+        if let Some(pkt) = packets.iter().find(|pkt| !pkt.len().is_multiple_of(BB_BLOCK_SIZE)) {
+            return Err(CipherError::InvalidSize { block_size: BB_BLOCK_SIZE, len: pkt.len() });
+        }
+        for pkt in packets.iter_mut() {
+            self.crypt_blocks(pkt, PSOBBCipher::decrypt_block)?;
         }
+        Ok(())
+    }
+}
 
-        Ok(result)
+impl PSOCipher for PSOBBCipher {
+    fn encrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        self.crypt_blocks(data, PSOBBCipher::encrypt_block)
+    }
+
+    fn decrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        self.crypt_blocks(data, PSOBBCipher::decrypt_block)
     }
 
     fn header_size(&self) -> usize {
@@ -180,14 +264,168 @@ mod tests {
         let mut cipher_out = PSOBBCipher::new(p, s, seed);
 
         for _ in 0..50 {
-            let len = (rng.gen::<u16>() / 4) * 4;
+            // small enough that padding to the block size still fits the u16 size field
+            let len = (rng.gen_range(0, 0xFFF8u16) / 4) * 4;
 
             let mut random_junk = vec![0u8; len as usize];
             rng.fill_bytes(&mut random_junk);
+            super::pad_packet(&mut random_junk).unwrap();
 
             let enc_data = cipher_in.encrypt(&random_junk).unwrap();
             let orig_data = cipher_out.decrypt(&enc_data).unwrap();
-            assert!(random_junk == orig_data[..len as usize].to_vec());
+            assert!(random_junk == orig_data);
+        }
+    }
+
+    #[test]
+    fn test_known_blocks() {
+        use super::{PSOCipher, PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+
+        let key_file = (0..BB_KEY_FILE_SIZE).map(|i| (i * 31 + 1) as u8).collect::<Vec<_>>();
+        let mut seed = [0u8; super::BB_SEED_SIZE];
+        for (i, s) in seed.iter_mut().enumerate() {
+            *s = i as u8;
         }
+        let key = BBKeyTable::from_bytes(&key_file).unwrap();
+        let data = (0..32).collect::<Vec<u8>>();
+
+        let enc_data = PSOBBCipher::with_key(&key, seed).encrypt(&data).unwrap();
+        assert!(enc_data == vec![0x6E, 0xFE, 0x66, 0xC5, 0x4C, 0xB6, 0xC1, 0x26, 0xBA, 0x4F, 0x32, 0x80, 0xD3, 0x62, 0xB5, 0x8C,
+                                 0x63, 0x72, 0x55, 0xFE, 0xF0, 0xB3, 0x15, 0x27, 0xAC, 0x8B, 0x3B, 0x09, 0x74, 0x4E, 0x01, 0x84]);
+        assert!(PSOBBCipher::with_key(&key, seed).decrypt(&enc_data).unwrap() == data);
+    }
+
+    #[test]
+    fn test_batch() {
+        use super::{PSOCipher, PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+
+        let key_file = (0..BB_KEY_FILE_SIZE).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let key = BBKeyTable::from_bytes(&key_file).unwrap();
+        let cipher = PSOBBCipher::with_key(&key, [3; super::BB_SEED_SIZE]);
+
+        let orig = vec![vec![1u8; 16], vec![2u8; 0x400], vec![]];
+        let mut packets = orig.clone();
+        {
+            let mut bufs = packets.iter_mut().map(|p| p.as_mut_slice()).collect::<Vec<_>>();
+            cipher.encrypt_batch(&mut bufs).unwrap();
+        }
+        for (enc, pkt) in packets.iter().zip(orig.iter()) {
+            assert!(*enc == cipher.clone().encrypt(pkt).unwrap());
+        }
+
+        let mut bufs = packets.iter_mut().map(|p| p.as_mut_slice()).collect::<Vec<_>>();
+        cipher.decrypt_batch(&mut bufs).unwrap();
+        assert!(packets == orig);
+
+        let mut odd = [0u8; 12];
+        let mut even = [0u8; 8];
+        assert!(cipher.encrypt_batch(&mut [&mut even, &mut odd]).is_err());
+        assert!(even == [0u8; 8]);
+    }
+
+    #[test]
+    fn test_padding() {
+        use rand::Rng;
+        use super::{PSOCipher, CipherError, PSOBBCipher, pad_packet};
+
+        let mut rng = rand::thread_rng();
+        let mut seed = [0u8; super::BB_SEED_SIZE];
+        rng.fill(&mut seed[..]);
+        let mut cipher = PSOBBCipher::new([0; 18], [[0; super::BB_SBOX_SIZE]; super::BB_SBOX_COUNT], seed);
+
+        let mut pkt = vec![0x0C, 0x00, 0xE8, 0x02, 0, 0, 0, 0, 1, 0, 0, 0];
+        assert!(matches!(cipher.encrypt(&pkt), Err(CipherError::InvalidSize { block_size: 8, len: 12 })));
+        assert!(cipher.encrypt(&pkt[..5]).is_err());
+        assert!(cipher.decrypt(&pkt).is_err());
+
+        pad_packet(&mut pkt).unwrap();
+        assert!(pkt == vec![0x10, 0x00, 0xE8, 0x02, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(cipher.encrypt(&pkt).is_ok());
+
+        pad_packet(&mut pkt).unwrap();
+        assert!(pkt.len() == 16);
+    }
+
+    #[test]
+    fn test_in_place() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOBBCipher};
+
+        let mut rng = rand::thread_rng();
+
+        let mut p = [0u32; 18];
+        let mut s = [[0u32; super::BB_SBOX_SIZE]; super::BB_SBOX_COUNT];
+        let mut seed = [0u8; super::BB_SEED_SIZE];
+        rng.fill(&mut p[..]);
+        for sbox in s.iter_mut() {
+            rng.fill(&mut sbox[..]);
+        }
+        rng.fill(&mut seed[..]);
+
+        let mut cipher_vec = PSOBBCipher::new(p, s, seed);
+        let mut cipher_in = PSOBBCipher::new(p, s, seed);
+        let mut cipher_out = PSOBBCipher::new(p, s, seed);
+
+        let mut random_junk = vec![0u8; 0x400];
+        rng.fill_bytes(&mut random_junk);
+
+        let mut data = random_junk.clone();
+        cipher_in.encrypt_in_place(&mut data).unwrap();
+        assert!(data == cipher_vec.encrypt(&random_junk).unwrap());
+        cipher_out.decrypt_in_place(&mut data).unwrap();
+        assert!(data == random_junk);
+
+        assert!(cipher_in.encrypt_in_place(&mut [0u8; 12]).is_err());
+    }
+
+    #[test]
+    fn test_state_restore() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+
+        let mut rng = rand::thread_rng();
+        let mut key_file = vec![0u8; BB_KEY_FILE_SIZE];
+        let mut seed = [0u8; super::BB_SEED_SIZE];
+        rng.fill_bytes(&mut key_file);
+        rng.fill(&mut seed[..]);
+
+        let mut cipher = PSOBBCipher::with_key(&BBKeyTable::from_bytes(&key_file).unwrap(), seed);
+        let mut restored = PSOBBCipher::from_bytes(&cipher.as_bytes()).unwrap();
+        let mut cloned = cipher.clone();
+        let data = vec![0u8; 400];
+        let expected = cipher.encrypt(&data).unwrap();
+        assert!(restored.encrypt(&data).unwrap() == expected);
+        assert!(cloned.encrypt(&data).unwrap() == expected);
+        assert!(PSOBBCipher::from_bytes(&key_file[1..]).is_err());
+    }
+
+    #[test]
+    fn test_key_file() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+
+        let mut rng = rand::thread_rng();
+
+        let mut key_file = vec![0u8; BB_KEY_FILE_SIZE + 4];
+        let mut seed = [0u8; super::BB_SEED_SIZE];
+        rng.fill_bytes(&mut key_file);
+        rng.fill(&mut seed[..]);
+
+        let path = std::env::temp_dir().join(format!("libpso-test-{}.nsk", rng.gen::<u32>()));
+        std::fs::write(&path, &key_file).unwrap();
+        let key = BBKeyTable::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(key.as_bytes() == key_file[..BB_KEY_FILE_SIZE].to_vec());
+        assert!(key.p_array[1] == u32::from_le_bytes([key_file[4], key_file[5], key_file[6], key_file[7]]));
+        assert!(key.sbox[3][255] == u32::from_le_bytes([key_file[BB_KEY_FILE_SIZE - 4], key_file[BB_KEY_FILE_SIZE - 3],
+                                                        key_file[BB_KEY_FILE_SIZE - 2], key_file[BB_KEY_FILE_SIZE - 1]]));
+
+        let mut cipher_in = PSOBBCipher::with_key(&key, seed);
+        let mut cipher_out = PSOBBCipher::new(key.p_array, key.sbox, seed);
+        let data = vec![7u8; 64];
+        assert!(cipher_out.decrypt(&cipher_in.encrypt(&data).unwrap()).unwrap() == data);
+
+        assert!(BBKeyTable::from_bytes(&key_file[..100]).is_err());
     }
 }
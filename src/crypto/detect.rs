@@ -0,0 +1,216 @@
+// figures out which cipher (and for bb, which key table) a client is using by decrypting the
+// header of the first packet it sends with every candidate and checking that it looks sane.
+
+use crate::crypto::PSOCipher;
+use crate::crypto::pc::PSOPCCipher;
+use crate::crypto::gc::PSOGCCipher;
+use crate::crypto::bb::{PSOBBCipher, BBKeyTable, BB_BLOCK_SIZE};
+
+const MAX_FIRST_PACKET_SIZE: usize = 0x1000;
+
+// commands a client may open a session with
+const PC_FIRST_COMMANDS: [u8; 3] = [0x9A, 0x9C, 0x9D];
+const GC_FIRST_COMMANDS: [u8; 5] = [0x9A, 0x9C, 0x9D, 0x9E, 0xDB];
+const BB_LOGIN_COMMAND: u16 = 0x93;
+const BB_LOGIN_SIZE: usize = 0xB4;
+
+// the client key from the server's welcome packet
+#[derive(Debug, Clone, Copy)]
+pub enum WelcomeKey {
+    V2V3(u32),
+    BB([u8; 48]),
+}
+
+// the detected cipher, not yet advanced: the first packet still has to be decrypted with it
+pub enum DetectedCipher {
+    PC(Box<PSOPCCipher>),
+    GC(Box<PSOGCCipher>),
+    // index into the detector's bb key tables
+    BB(Box<PSOBBCipher>, usize),
+}
+
+impl std::fmt::Debug for DetectedCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DetectedCipher::PC(_) => write!(f, "PC"),
+            DetectedCipher::GC(_) => write!(f, "GC"),
+            DetectedCipher::BB(_, key) => write!(f, "BB({})", key),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CipherDetector {
+    bb_keys: Vec<BBKeyTable>,
+}
+
+impl CipherDetector {
+    pub fn new() -> CipherDetector {
+        CipherDetector {
+            bb_keys: Vec::new(),
+        }
+    }
+
+    pub fn with_bb_key(mut self, key: BBKeyTable) -> CipherDetector {
+        self.bb_keys.push(key);
+        self
+    }
+
+    pub fn bb_keys(&self) -> &[BBKeyTable] {
+        &self.bb_keys
+    }
+
+    pub fn detect(&self, key: WelcomeKey, first_packet: &[u8]) -> Option<DetectedCipher> {
+        match key {
+            WelcomeKey::V2V3(key) => {
+                let pc = decrypt_header(PSOPCCipher::new(key), first_packet)
+                    .map(|h| score_v2_v3(u16::from_le_bytes([h[0], h[1]]), h[2], &PC_FIRST_COMMANDS))
+                    .unwrap_or(0);
+                let gc = decrypt_header(PSOGCCipher::new(key), first_packet)
+                    .map(|h| score_v2_v3(u16::from_le_bytes([h[2], h[3]]), h[0], &GC_FIRST_COMMANDS))
+                    .unwrap_or(0);
+
+                if pc == 0 && gc == 0 {
+                    None
+                }
+                else if pc >= gc {
+                    Some(DetectedCipher::PC(Box::new(PSOPCCipher::new(key))))
+                }
+                else {
+                    Some(DetectedCipher::GC(Box::new(PSOGCCipher::new(key))))
+                }
+            },
+            WelcomeKey::BB(seed) => {
+                self.bb_keys.iter()
+                    .enumerate()
+                    .filter_map(|(i, table)| {
+                        let h = decrypt_header(PSOBBCipher::with_key(table, seed), first_packet)?;
+                        Some((i, score_bb(u16::from_le_bytes([h[0], h[1]]), u16::from_le_bytes([h[2], h[3]]))))
+                    })
+                    .filter(|(_, score)| *score > 0)
+                    .max_by_key(|(i, score)| (*score, std::cmp::Reverse(*i)))
+                    .map(|(i, _)| DetectedCipher::BB(Box::new(PSOBBCipher::with_key(&self.bb_keys[i], seed)), i))
+            },
+        }
+    }
+}
+
+fn decrypt_header<C: PSOCipher>(mut cipher: C, data: &[u8]) -> Option<Vec<u8>> {
+    let mut header = data.get(..cipher.header_size())?.to_vec();
+    cipher.decrypt_in_place(&mut header).ok()?;
+    Some(header)
+}
+
+fn score_v2_v3(size: u16, cmd: u8, first_commands: &[u8]) -> u32 {
+    let size = size as usize;
+    if !(4..=MAX_FIRST_PACKET_SIZE).contains(&size) || !size.is_multiple_of(4) {
+        0
+    }
+    else if first_commands.contains(&cmd) {
+        2
+    }
+    else {
+        1
+    }
+}
+
+fn score_bb(size: u16, cmd: u16) -> u32 {
+    let size = size as usize;
+    if !(8..=MAX_FIRST_PACKET_SIZE).contains(&size) || !size.is_multiple_of(4) {
+        0
+    }
+    // clients may or may not count the padding in the size
+    else if cmd == BB_LOGIN_COMMAND && size.div_ceil(BB_BLOCK_SIZE) == BB_LOGIN_SIZE.div_ceil(BB_BLOCK_SIZE) {
+        3
+    }
+    else if cmd == BB_LOGIN_COMMAND {
+        2
+    }
+    else {
+        1
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::PSOCipher;
+    use super::{CipherDetector, DetectedCipher, WelcomeKey};
+
+    fn v2_v3_packet() -> Vec<u8> {
+        let mut pkt = vec![0u8; 0x20];
+        pkt[0] = 0x20;
+        pkt[2] = 0x9D;
+        pkt
+    }
+
+    #[test]
+    fn test_detect_pc() {
+        use crate::crypto::pc::PSOPCCipher;
+
+        let pkt = PSOPCCipher::new(0x11223344).encrypt(&v2_v3_packet()).unwrap();
+        let detected = CipherDetector::new().detect(WelcomeKey::V2V3(0x11223344), &pkt);
+        if let Some(DetectedCipher::PC(mut cipher)) = detected {
+            assert!(cipher.decrypt(&pkt).unwrap() == v2_v3_packet());
+        }
+        else {
+            panic!("detected {:?}", detected);
+        }
+    }
+
+    #[test]
+    fn test_detect_gc() {
+        use crate::crypto::gc::PSOGCCipher;
+
+        // gc headers are cmd, flag, size
+        let mut header = v2_v3_packet();
+        header[..4].copy_from_slice(&[0x9E, 0x00, 0x20, 0x00]);
+        let pkt = PSOGCCipher::new(0x11223344).encrypt(&header).unwrap();
+        let detected = CipherDetector::new().detect(WelcomeKey::V2V3(0x11223344), &pkt);
+        assert!(matches!(detected, Some(DetectedCipher::GC(_))));
+    }
+
+    #[test]
+    fn test_detect_bb_key() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+        use crate::PSOPacket;
+        use crate::crypto::bb::{PSOBBCipher, BBKeyTable, BB_KEY_FILE_SIZE};
+        use crate::packet::login::Login;
+        use crate::text::FixedStr;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut detector = CipherDetector::new();
+        for _ in 0..3 {
+            let mut key_file = vec![0u8; BB_KEY_FILE_SIZE];
+            rng.fill(&mut key_file[..]);
+            detector = detector.with_bb_key(BBKeyTable::from_bytes(&key_file).unwrap());
+        }
+
+        let mut seed = [0u8; 48];
+        rng.fill(&mut seed[..]);
+
+        let login = Login {
+            flag: 0,
+            tag: 0x00010000,
+            guildcard: 0,
+            version: 0x41,
+            unknown1: [0; 6],
+            team: 0,
+            username: FixedStr::new("user").unwrap(),
+            unknown2: [0; 32],
+            password: FixedStr::default(),
+            unknown3: [0; 40],
+            hwinfo: [0; 8],
+            security_data: [0; 40],
+        };
+        let bytes = login.as_bytes();
+        let pkt = PSOBBCipher::with_key(&detector.bb_keys()[1], seed).encrypt(&bytes).unwrap();
+
+        match detector.detect(WelcomeKey::BB(seed), &pkt) {
+            Some(DetectedCipher::BB(mut cipher, 1)) => assert!(cipher.decrypt(&pkt).unwrap() == bytes),
+            other => panic!("detected {:?}", other),
+        }
+        assert!(detector.detect(WelcomeKey::BB(seed), &pkt[..4]).is_none());
+    }
+}
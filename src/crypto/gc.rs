@@ -0,0 +1,195 @@
+// implementation taken from kohle's newserv
+// https://github.com/fuzziqersoftware/newserv/
+
+use crate::crypto::{PSOCipher, CipherError};
+use std::num::Wrapping as W;
+
+const GC_STREAM_LENGTH: usize = 521;
+pub const GC_STATE_SIZE: usize = GC_STREAM_LENGTH * 4 + 2;
+
+#[derive(Clone)]
+pub struct PSOGCCipher {
+    stream: [u32; GC_STREAM_LENGTH],
+    offset: u16,
+}
+
+impl PSOGCCipher {
+    pub fn new(seed: u32) -> PSOGCCipher {
+        let mut stream: [u32; GC_STREAM_LENGTH] = [0; GC_STREAM_LENGTH];
+        let mut seed = W(seed);
+        let mut basekey: u32 = 0;
+
+        for key in stream.iter_mut().take(17) {
+            for _ in 0..32 {
+                seed = seed * W(0x5D588B65) + W(1);
+                basekey >>= 1;
+                if seed.0 & 0x80000000 != 0 {
+                    basekey |= 0x80000000;
+                }
+            }
+            *key = basekey;
+        }
+
+        stream[16] = (stream[0] >> 9) ^ (stream[16] << 23) ^ stream[15];
+
+        for i in 17..GC_STREAM_LENGTH {
+            stream[i] = stream[i - 1] ^ ((stream[i - 17] << 23) & 0xFF800000) ^ ((stream[i - 16] >> 9) & 0x007FFFFF);
+        }
+
+        let mut cipher = PSOGCCipher {
+            stream,
+            offset: 0,
+        };
+
+        for _ in 0..3 {
+            cipher.update_stream();
+        }
+        // like newserv and sylverant, the first key is the last word of the mixed stream
+        cipher.offset = 520;
+
+        cipher
+    }
+
+    // the stream as little endian u32s followed by the u16 offset
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(GC_STATE_SIZE);
+        for k in self.stream.iter() {
+            result.extend_from_slice(&k.to_le_bytes());
+        }
+        result.extend_from_slice(&self.offset.to_le_bytes());
+        result
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<PSOGCCipher, CipherError> {
+        if data.len() != GC_STATE_SIZE {
+            return Err(CipherError::InvalidState);
+        }
+
+        let mut stream = [0u32; GC_STREAM_LENGTH];
+        for (dst, k) in stream.iter_mut().zip(data.chunks_exact(4)) {
+            *dst = u32::from_le_bytes([k[0], k[1], k[2], k[3]]);
+        }
+        let offset = u16::from_le_bytes([data[GC_STATE_SIZE - 2], data[GC_STATE_SIZE - 1]]);
+        if offset as usize > GC_STREAM_LENGTH {
+            return Err(CipherError::InvalidState);
+        }
+
+        Ok(PSOGCCipher {
+            stream,
+            offset,
+        })
+    }
+
+    fn update_stream(&mut self) {
+        for i in 0..(GC_STREAM_LENGTH - 489) {
+            self.stream[i] ^= self.stream[i + 489];
+        }
+        for i in (GC_STREAM_LENGTH - 489)..GC_STREAM_LENGTH {
+            self.stream[i] ^= self.stream[i - (GC_STREAM_LENGTH - 489)];
+        }
+        self.offset = 0;
+    }
+
+    fn next(&mut self) -> u32 {
+        if self.offset as usize == GC_STREAM_LENGTH {
+            self.update_stream();
+        }
+
+        let result = self.stream[self.offset as usize];
+        self.offset += 1;
+        result
+    }
+}
+
+impl PSOCipher for PSOGCCipher {
+    fn encrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        if !data.len().is_multiple_of(4) {
+            return Err(CipherError::InvalidSize { block_size: 4, len: data.len() })
+        }
+
+        for c in data.chunks_exact_mut(4) {
+            let mut data = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+            data ^= self.next();
+            c.copy_from_slice(&u32::to_le_bytes(data));
+        }
+        Ok(())
+    }
+
+    fn decrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        self.encrypt_in_place(data)
+    }
+
+    fn header_size(&self) -> usize {
+        4
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_crypto() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOGCCipher};
+
+        let mut rng = rand::thread_rng();
+
+        let seed: u32 = rng.gen();
+        let mut cipher_in = PSOGCCipher::new(seed);
+        let mut cipher_out = PSOGCCipher::new(seed);
+
+        // long enough to wrap the 521 word stream a few times
+        for _ in 0..100 {
+            let mut random_junk = vec![0u8; 40];
+            rng.fill_bytes(&mut random_junk);
+
+            let enc_data = cipher_in.encrypt(&random_junk).unwrap();
+            let orig_data = cipher_out.decrypt(&enc_data).unwrap();
+
+            assert!(random_junk == orig_data);
+        }
+    }
+
+    #[test]
+    fn test_start_offset() {
+        use super::{PSOCipher, PSOGCCipher, GC_STATE_SIZE};
+
+        let mut cipher = PSOGCCipher::new(0x12345678);
+        let state = cipher.as_bytes();
+        let word = |i: usize| u32::from_le_bytes([state[i * 4], state[i * 4 + 1], state[i * 4 + 2], state[i * 4 + 3]]);
+        assert!(state[GC_STATE_SIZE - 2..] == 520u16.to_le_bytes());
+
+        // word 520 first, then the stream is mixed again before word 0 is used
+        let keys = cipher.encrypt(&[0u8; 8]).unwrap();
+        assert!(keys[..4] == word(520).to_le_bytes());
+        assert!(keys[4..] == (word(0) ^ word(489)).to_le_bytes());
+    }
+
+    #[test]
+    fn test_state_restore() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOGCCipher};
+
+        let mut rng = rand::thread_rng();
+        let mut cipher = PSOGCCipher::new(rng.gen());
+        let mut junk = vec![0u8; 4 * rng.gen_range(0, 1000)];
+        rng.fill_bytes(&mut junk);
+        cipher.encrypt(&junk).unwrap();
+
+        let mut restored = PSOGCCipher::from_bytes(&cipher.as_bytes()).unwrap();
+        let mut cloned = cipher.clone();
+        let data = vec![0u8; 4000];
+        let expected = cipher.encrypt(&data).unwrap();
+        assert!(restored.encrypt(&data).unwrap() == expected);
+        assert!(cloned.encrypt(&data).unwrap() == expected);
+        assert!(PSOGCCipher::from_bytes(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        use super::{PSOCipher, PSOGCCipher};
+
+        let mut cipher = PSOGCCipher::new(0);
+        assert!(cipher.encrypt(&[0u8; 6]).is_err());
+    }
+}
@@ -1,17 +1,81 @@
 pub mod pc;
+pub mod gc;
+pub mod detect;
 pub mod bb;
 
+use crate::HeaderKind;
+
+
+// pads a packet with zeros to a multiple of block_size and rewrites the u16 size in the header to
+// the padded length, which has to fit in that u16
+pub fn pad_packet(packet: &mut Vec<u8>, header: HeaderKind, block_size: usize) -> Result<(), CipherError> {
+    if block_size == 0 {
+        return Err(CipherError::InvalidSize { block_size, len: packet.len() });
+    }
+    let len = packet.len().div_ceil(block_size) * block_size;
+    if len > u16::MAX as usize {
+        return Err(CipherError::PacketTooLarge(len));
+    }
+
+    packet.resize(len, 0);
+    let offset = header.size_offset();
+    if len >= offset + 2 {
+        packet[offset..offset + 2].copy_from_slice(&(len as u16).to_le_bytes());
+    }
+    Ok(())
+}
+
 
 #[derive(Debug)]
 pub enum CipherError {
-    InvalidSize
+    InvalidSize {
+        block_size: usize,
+        len: usize,
+    },
+    PacketTooLarge(usize),
+    InvalidKeyFile(usize),
+    InvalidState,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for CipherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CipherError::InvalidSize { block_size, len } => write!(f, "{} bytes is not a multiple of the {} byte block size", len, block_size),
+            CipherError::PacketTooLarge(len) => write!(f, "{} byte packet does not fit the u16 size field", len),
+            CipherError::InvalidKeyFile(len) => write!(f, "bb key table is {} bytes, expected at least {}", len, bb::BB_KEY_FILE_SIZE),
+            CipherError::InvalidState => write!(f, "invalid cipher state"),
+            CipherError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CipherError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CipherError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 
 
 pub trait PSOCipher {
-    fn encrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError>;
-    fn decrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError>;
+    fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let mut result = data.to_vec();
+        self.encrypt_in_place(&mut result)?;
+        Ok(result)
+    }
+
+    fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let mut result = data.to_vec();
+        self.decrypt_in_place(&mut result)?;
+        Ok(result)
+    }
+
+    fn encrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError>;
+    fn decrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError>;
     fn header_size(&self) -> usize;
     fn block_size(&self) -> usize {
         self.header_size()
@@ -20,19 +84,72 @@ pub trait PSOCipher {
 
 
 
+impl<C: PSOCipher + ?Sized> PSOCipher for Box<C> {
+    fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        (**self).encrypt(data)
+    }
+
+    fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        (**self).decrypt(data)
+    }
+
+    fn encrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        (**self).encrypt_in_place(data)
+    }
+
+    fn decrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        (**self).decrypt_in_place(data)
+    }
+
+    fn header_size(&self) -> usize {
+        (**self).header_size()
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+}
+
+
+
+#[derive(Clone)]
 pub struct NullCipher {
 }
 
 impl PSOCipher for NullCipher {
-    fn encrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        Ok(data.clone())
+    fn encrypt_in_place(&mut self, _data: &mut [u8]) -> Result<(), CipherError> {
+        Ok(())
     }
 
-    fn decrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        Ok(data.clone())
+    fn decrypt_in_place(&mut self, _data: &mut [u8]) -> Result<(), CipherError> {
+        Ok(())
     }
 
     fn header_size(&self) -> usize {
         4
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_pad_packet() {
+        use crate::HeaderKind;
+        use super::{CipherError, pad_packet};
+
+        let mut pkt = vec![0x06, 0x00, 0x05, 0x00, 1, 2];
+        pad_packet(&mut pkt, HeaderKind::PC, 4).unwrap();
+        assert!(pkt == vec![0x08, 0x00, 0x05, 0x00, 1, 2, 0, 0]);
+
+        // dc and gc keep cmd and flag ahead of the size
+        let mut pkt = vec![0x93, 0x01, 0x06, 0x00, 1, 2];
+        pad_packet(&mut pkt, HeaderKind::GC, 4).unwrap();
+        assert!(pkt == vec![0x93, 0x01, 0x08, 0x00, 1, 2, 0, 0]);
+
+        let mut pkt = vec![0u8; 0xFFFE];
+        assert!(matches!(pad_packet(&mut pkt, HeaderKind::BB, 8), Err(CipherError::PacketTooLarge(0x10000))));
+        assert!(pkt.len() == 0xFFFE);
+        assert!(matches!(pad_packet(&mut pkt, HeaderKind::BB, 0), Err(CipherError::InvalidSize { block_size: 0, .. })));
+    }
+}
@@ -5,7 +5,9 @@ use crate::crypto::{PSOCipher, CipherError};
 use std::num::Wrapping as W;
 
 const PC_STREAM_LENGTH: usize = 57;
+pub const PC_STATE_SIZE: usize = PC_STREAM_LENGTH * 4 + 2;
 
+#[derive(Clone)]
 pub struct PSOPCCipher {
   stream: [u32; PC_STREAM_LENGTH],
   offset: u16,
@@ -31,15 +33,15 @@ impl PSOPCCipher {
             eax = edi;
             var1 = eax / W(55);
             edx = eax - (var1 * W(55));
-            ebx = ebx - esi;
-            edi = edi + W(0x15);
+            ebx -= esi;
+            edi += W(0x15);
             stream[edx.0 as usize] = esi.0;
             esi = ebx;
             ebx = W(stream[edx.0 as usize]);
         }
 
         let mut cipher = PSOPCCipher {
-            stream: stream,
+            stream,
             offset: 1,
         };
         
@@ -49,7 +51,37 @@ impl PSOPCCipher {
 
         cipher
     }
-   
+
+    // the stream as little endian u32s followed by the u16 offset
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(PC_STATE_SIZE);
+        for k in self.stream.iter() {
+            result.extend_from_slice(&k.to_le_bytes());
+        }
+        result.extend_from_slice(&self.offset.to_le_bytes());
+        result
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<PSOPCCipher, CipherError> {
+        if data.len() != PC_STATE_SIZE {
+            return Err(CipherError::InvalidState);
+        }
+
+        let mut stream = [0u32; PC_STREAM_LENGTH];
+        for (dst, k) in stream.iter_mut().zip(data.chunks_exact(4)) {
+            *dst = u32::from_le_bytes([k[0], k[1], k[2], k[3]]);
+        }
+        let offset = u16::from_le_bytes([data[PC_STATE_SIZE - 2], data[PC_STATE_SIZE - 1]]);
+        if offset as usize >= PC_STREAM_LENGTH {
+            return Err(CipherError::InvalidState);
+        }
+
+        Ok(PSOPCCipher {
+            stream,
+            offset,
+        })
+    }
+
     fn update_stream(&mut self) {
         let mut esi: W<u32>;
         let mut edi: W<u32>;
@@ -63,7 +95,7 @@ impl PSOPCCipher {
         while edx > W(0) {
             esi = W(self.stream[eax.0 as usize + 0x1F]);
             ebp = W(self.stream[eax.0 as usize]);
-            ebp = ebp - esi;
+            ebp -= esi;
             self.stream[eax.0 as usize] = ebp.0;
             eax += W(1);
             edx -= W(1);
@@ -74,7 +106,7 @@ impl PSOPCCipher {
         while edx > W(0) {
             esi = W(self.stream[eax.0 as usize - 0x18]);
             ebp = W(self.stream[eax.0 as usize]);
-            ebp = ebp - esi;
+            ebp -= esi;
             self.stream[eax.0 as usize] = ebp.0;
             eax += W(1);
             edx -= W(1);
@@ -94,22 +126,21 @@ impl PSOPCCipher {
 }
 
 impl PSOCipher for PSOPCCipher {
-    fn encrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        let mut result = Vec::new();
-        if data.len() % 4 != 0 {
-            return Err(CipherError::InvalidSize)
+    fn encrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        if !data.len().is_multiple_of(4) {
+            return Err(CipherError::InvalidSize { block_size: 4, len: data.len() })
         }
 
-        for c in data.chunks(4) {
+        for c in data.chunks_exact_mut(4) {
             let mut data = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
             data ^= self.next();
-            result.extend_from_slice(&u32::to_le_bytes(data));
+            c.copy_from_slice(&u32::to_le_bytes(data));
         }
-        Ok(result)
+        Ok(())
     }
-    
-    fn decrypt(&mut self, data: &Vec<u8>) -> Result<Vec<u8>, CipherError> {
-        self.encrypt(data)
+
+    fn decrypt_in_place(&mut self, data: &mut [u8]) -> Result<(), CipherError> {
+        self.encrypt_in_place(data)
     }
 
     fn header_size(&self) -> usize {
@@ -141,6 +172,53 @@ mod tests {
             assert!(random_junk == orig_data);
         }
     }
+
+    #[test]
+    fn test_in_place() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOPCCipher};
+
+        let mut rng = rand::thread_rng();
+
+        let seed: u32 = rng.gen();
+        let mut cipher_vec = PSOPCCipher::new(seed);
+        let mut cipher_in = PSOPCCipher::new(seed);
+        let mut cipher_out = PSOPCCipher::new(seed);
+
+        let mut random_junk = vec![0u8; 400];
+        rng.fill_bytes(&mut random_junk);
+
+        let mut data = random_junk.clone();
+        cipher_in.encrypt_in_place(&mut data).unwrap();
+        assert!(data == cipher_vec.encrypt(&random_junk).unwrap());
+        cipher_out.decrypt_in_place(&mut data).unwrap();
+        assert!(data == random_junk);
+    }
+
+    #[test]
+    fn test_state_restore() {
+        use rand::{Rng, RngCore};
+        use super::{PSOCipher, PSOPCCipher};
+
+        let mut rng = rand::thread_rng();
+        let mut cipher = PSOPCCipher::new(rng.gen());
+        let mut junk = vec![0u8; 4 * rng.gen_range(0, 200)];
+        rng.fill_bytes(&mut junk);
+        cipher.encrypt(&junk).unwrap();
+
+        let mut restored = PSOPCCipher::from_bytes(&cipher.as_bytes()).unwrap();
+        let mut cloned = cipher.clone();
+        let data = vec![0u8; 400];
+        let expected = cipher.encrypt(&data).unwrap();
+        assert!(restored.encrypt(&data).unwrap() == expected);
+        assert!(cloned.encrypt(&data).unwrap() == expected);
+
+        let mut bad_state = cipher.as_bytes();
+        assert!(PSOPCCipher::from_bytes(&bad_state[1..]).is_err());
+        let len = bad_state.len();
+        bad_state[len - 2] = 0xFF;
+        assert!(PSOPCCipher::from_bytes(&bad_state).is_err());
+    }
 }
 
 
@@ -0,0 +1,102 @@
+// c abi over the ciphers, built with the ffi feature. handles are opaque, every one returned from a
+// *_new function has to be handed back to pso_cipher_free. libpso stays an rlib, c users build the
+// shared library with `cargo rustc --release --lib --features ffi --crate-type cdylib`. build.rs
+// generates libpso.h from this file into OUT_DIR, the copy in include/ is checked against it by
+// tests/ffi.rs.
+
+use crate::crypto::{PSOCipher, CipherError};
+use crate::crypto::pc::PSOPCCipher;
+use crate::crypto::bb::{PSOBBCipher, BBKeyTable};
+
+pub const PSO_OK: i32 = 0;
+pub const PSO_ERROR_NULL_POINTER: i32 = -1;
+pub const PSO_ERROR_INVALID_SIZE: i32 = -2;
+pub const PSO_ERROR_INVALID_KEY: i32 = -3;
+
+pub struct PSOCipherHandle {
+    cipher: Box<dyn PSOCipher + Send>,
+}
+
+fn into_handle<C: PSOCipher + Send + 'static>(cipher: C) -> *mut PSOCipherHandle {
+    Box::into_raw(Box::new(PSOCipherHandle {
+        cipher: Box::new(cipher),
+    }))
+}
+
+fn error_code(err: CipherError) -> i32 {
+    match err {
+        CipherError::InvalidSize { .. } | CipherError::PacketTooLarge(_) => PSO_ERROR_INVALID_SIZE,
+        _ => PSO_ERROR_INVALID_KEY,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pso_pc_cipher_new(seed: u32) -> *mut PSOCipherHandle {
+    into_handle(PSOPCCipher::new(seed))
+}
+
+/// # Safety
+/// `key_table` must point to `key_table_len` readable bytes in the .nsk layout and `seed` to
+/// 48 readable bytes. returns null if either is null or the key table is too short.
+#[no_mangle]
+pub unsafe extern "C" fn pso_bb_cipher_new(key_table: *const u8, key_table_len: usize, seed: *const u8) -> *mut PSOCipherHandle {
+    if key_table.is_null() || seed.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let key = match BBKeyTable::from_bytes(std::slice::from_raw_parts(key_table, key_table_len)) {
+        Ok(key) => key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let mut bb_seed = [0u8; 48];
+    bb_seed.copy_from_slice(std::slice::from_raw_parts(seed, 48));
+
+    into_handle(PSOBBCipher::with_key(&key, bb_seed))
+}
+
+/// # Safety
+/// `cipher` must be a live handle and `data` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pso_cipher_encrypt(cipher: *mut PSOCipherHandle, data: *mut u8, len: usize) -> i32 {
+    if cipher.is_null() || data.is_null() {
+        return PSO_ERROR_NULL_POINTER;
+    }
+
+    match (*cipher).cipher.encrypt_in_place(std::slice::from_raw_parts_mut(data, len)) {
+        Ok(_) => PSO_OK,
+        Err(err) => error_code(err),
+    }
+}
+
+/// # Safety
+/// `cipher` must be a live handle and `data` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pso_cipher_decrypt(cipher: *mut PSOCipherHandle, data: *mut u8, len: usize) -> i32 {
+    if cipher.is_null() || data.is_null() {
+        return PSO_ERROR_NULL_POINTER;
+    }
+
+    match (*cipher).cipher.decrypt_in_place(std::slice::from_raw_parts_mut(data, len)) {
+        Ok(_) => PSO_OK,
+        Err(err) => error_code(err),
+    }
+}
+
+/// # Safety
+/// `cipher` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pso_cipher_header_size(cipher: *const PSOCipherHandle) -> usize {
+    if cipher.is_null() {
+        return 0;
+    }
+    (*cipher).cipher.header_size()
+}
+
+/// # Safety
+/// `cipher` must be null or a handle that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pso_cipher_free(cipher: *mut PSOCipherHandle) {
+    if !cipher.is_null() {
+        drop(Box::from_raw(cipher));
+    }
+}
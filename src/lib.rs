@@ -1,22 +1,309 @@
 pub mod crypto;
 pub mod packet;
 pub mod character;
+pub mod net;
+pub mod text;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use psopacket::{PSOPacketData, PSOEnum};
+
+// #[pso_packet] refers to the types below as ::libpso::..., which has to resolve in here too
+extern crate self as libpso;
+#[doc(hidden)]
+pub use inventory;
+
+use crate::crypto::CipherError;
+use crate::net::framer::FramingError;
+use crate::net::stream::StreamError;
+use crate::text::TextError;
 
 #[derive(Debug, PartialEq)]
 pub enum PacketParseError {
     NotEnoughBytes,
-    WrongPacketCommand,
-    WrongPacketForServerType,
-    WrongPacketSize(u16, usize),
-    DataStructNotLargeEnough(u64, usize),
+    WrongPacketCommand {
+        packet: &'static str,
+        expected: u16,
+        got: u16,
+    },
+    WrongPacketForServerType {
+        packet: &'static str,
+        server: ServerType,
+    },
+    WrongPacketSize {
+        packet: &'static str,
+        cmd: u16,
+        declared: u16,
+        len: usize,
+    },
+    DataStructNotLargeEnough {
+        packet: &'static str,
+        cmd: u16,
+        position: u64,
+        len: usize,
+    },
     InvalidValue,
+    Field {
+        packet: &'static str,
+        field: &'static str,
+        offset: u64,
+        error: Box<PacketParseError>,
+    },
 }
 
+impl std::fmt::Display for PacketParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PacketParseError::NotEnoughBytes => write!(f, "not enough bytes"),
+            PacketParseError::WrongPacketCommand { packet, expected, got } => {
+                write!(f, "{} expects command {:#X}, got {:#X}", packet, expected, got)
+            },
+            PacketParseError::WrongPacketForServerType { packet, server } => {
+                write!(f, "{} is only handled by the {:?} server", packet, server)
+            },
+            PacketParseError::WrongPacketSize { packet, cmd, declared, len } => {
+                write!(f, "{} ({:#X}) header declares {} bytes, got {}", packet, cmd, declared, len)
+            },
+            PacketParseError::DataStructNotLargeEnough { packet, cmd, position, len } => {
+                write!(f, "{} ({:#X}) only read {} of {} bytes", packet, cmd, position, len)
+            },
+            PacketParseError::InvalidValue => write!(f, "invalid value"),
+            PacketParseError::Field { packet, field, offset, error } => {
+                write!(f, "{}.{} at offset {:#X}: {}", packet, field, offset, error)
+            },
+        }
+    }
+}
 
-pub trait PSOPacket: std::fmt::Debug {
-    fn from_bytes(data: &Vec<u8>) -> Result<Self, PacketParseError> where Self: Sized;
+impl std::error::Error for PacketParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PacketParseError::Field { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+
+// everything this crate can fail with, for applications that would rather use one error type
+#[derive(Debug)]
+pub enum Error {
+    Cipher(CipherError),
+    Parse(PacketParseError),
+    Framing(FramingError),
+    Text(TextError),
+    Io(std::io::Error),
+    ConnectionClosed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Cipher(err) => write!(f, "cipher error: {}", err),
+            Error::Parse(err) => write!(f, "packet parse error: {}", err),
+            Error::Framing(err) => write!(f, "framing error: {}", err),
+            Error::Text(err) => write!(f, "text error: {}", err),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::ConnectionClosed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Cipher(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::Framing(err) => Some(err),
+            Error::Text(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::ConnectionClosed => None,
+        }
+    }
+}
+
+impl From<CipherError> for Error {
+    fn from(err: CipherError) -> Error {
+        Error::Cipher(err)
+    }
+}
+
+impl From<PacketParseError> for Error {
+    fn from(err: PacketParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<FramingError> for Error {
+    fn from(err: FramingError) -> Error {
+        match err {
+            FramingError::Cipher(err) => Error::Cipher(err),
+            err => Error::Framing(err),
+        }
+    }
+}
+
+impl From<TextError> for Error {
+    fn from(err: TextError) -> Error {
+        Error::Text(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<StreamError> for Error {
+    fn from(err: StreamError) -> Error {
+        match err {
+            StreamError::Io(err) => Error::Io(err),
+            StreamError::Framing(err) => Error::from(err),
+            StreamError::Cipher(err) => Error::Cipher(err),
+            StreamError::ConnectionClosed => Error::ConnectionClosed,
+        }
+    }
+}
+
+
+// plain data that can be embedded in a packet, usually through #[derive(PSOPacketData)]
+pub trait PSOPacketData {
+    fn from_bytes(cur: &mut std::io::Cursor<&[u8]>) -> Result<Self, PacketParseError> where Self: Sized;
     fn as_bytes(&self) -> Vec<u8>;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderKind {
+    Patch,
+    PC,
+    DC,
+    GC,
+    BB,
+}
 
+impl HeaderKind {
+    // where the u16 size sits in the header. dc and gc put the cmd and flag bytes in front of it.
+    pub fn size_offset(&self) -> usize {
+        match self {
+            HeaderKind::DC | HeaderKind::GC => 2,
+            HeaderKind::Patch | HeaderKind::PC | HeaderKind::BB => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerType {
+    Patch,
+    Login,
+}
+
+// what #[pso_packet] knows about a packet. every one of them registers its info on startup, see
+// packets(). server is None for packets that aren't tied to one server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    pub cmd: u16,
+    pub name: &'static str,
+    pub header: HeaderKind,
+    pub direction: Direction,
+    pub server: Option<ServerType>,
+}
 
+inventory::collect!(PacketInfo);
+
+pub fn packets() -> impl Iterator<Item = &'static PacketInfo> {
+    inventory::iter::<PacketInfo>.into_iter()
+}
+
+// the CMD, NAME, HEADER, DIRECTION, SERVER and INFO consts and the registry entry for a packet.
+// #[pso_packet] expands to this, a packet implementing PSOPacket by hand calls it directly.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! packet_info {
+    ($packet:ident, $cmd:expr, $header:expr, $direction:expr, $server:expr) => {
+        impl $packet {
+            pub const CMD: u16 = $cmd;
+            pub const NAME: &'static str = stringify!($packet);
+            pub const HEADER: $crate::HeaderKind = $header;
+            pub const DIRECTION: $crate::Direction = $direction;
+            pub const SERVER: Option<$crate::ServerType> = $server;
+            pub const INFO: $crate::PacketInfo = $crate::PacketInfo {
+                cmd: $packet::CMD,
+                name: $packet::NAME,
+                header: $packet::HEADER,
+                direction: $packet::DIRECTION,
+                server: $packet::SERVER,
+            };
+        }
+
+        $crate::inventory::submit! {
+            $packet::INFO
+        }
+    };
+}
+
+pub trait PSOPacket: std::fmt::Debug {
+    fn from_bytes(data: &[u8]) -> Result<Self, PacketParseError> where Self: Sized;
+
+    // appends the whole packet, header included, to the end of buf. panics on a packet too big for
+    // the u16 size field, the same limit crypto::pad_packet reports as PacketTooLarge, or a flag too
+    // big for the header
+    fn encode_into(&self, buf: &mut Vec<u8>);
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    fn write_to<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> where Self: Sized {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        w.write_all(&buf)
+    }
+
+    // buf is cleared and the packet encoded into it before it's written out, so a caller sending
+    // many packets can keep reusing one buffer instead of allocating for each
+    fn write_to_with_buf<W: std::io::Write>(&self, w: &mut W, buf: &mut Vec<u8>) -> std::io::Result<()> where Self: Sized {
+        buf.clear();
+        self.encode_into(buf);
+        w.write_all(buf)
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_error_conversions() {
+        use crate::{Error, PSOPacket, PacketParseError};
+        use crate::crypto::PSOCipher;
+        use crate::crypto::pc::PSOPCCipher;
+        use crate::packet::patch::{PatchWelcome, FileInfoReply};
+
+        fn parse_reply(data: &[u8]) -> Result<FileInfoReply, Error> {
+            let data = PSOPCCipher::new(0).decrypt(data)?;
+            Ok(FileInfoReply::from_bytes(&data)?)
+        }
+
+        let welcome = PSOPCCipher::new(0).encrypt(&PatchWelcome::new(1, 2).as_bytes()).unwrap();
+        match parse_reply(&welcome) {
+            Err(Error::Parse(PacketParseError::WrongPacketCommand { packet: "FileInfoReply", expected: 0x0F, got: 0x02 })) => {},
+            other => panic!("{:?}", other),
+        }
+        match parse_reply(&welcome[..6]) {
+            Err(err @ Error::Cipher(_)) => assert!(err.to_string() == "cipher error: 6 bytes is not a multiple of the 4 byte block size"),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{PSOPacket, HeaderKind};
+use crate::crypto::{PSOCipher, NullCipher};
+use crate::net::encrypt_packet;
+use crate::net::framer::PacketFramer;
+use crate::net::stream::StreamError;
+
+
+// tokio counterpart to PSOStream. decoded items are whole decrypted packets ready for
+// PSOPacket::from_bytes. swap the ciphers through Framed::codec_mut once the welcome is sent.
+pub struct PSOCodec {
+    framer: PacketFramer<Box<dyn PSOCipher + Send>>,
+    outbound: Box<dyn PSOCipher + Send>,
+}
+
+impl PSOCodec {
+    pub fn new() -> PSOCodec {
+        PSOCodec::with_ciphers(Box::new(NullCipher {}), Box::new(NullCipher {}))
+    }
+
+    pub fn with_ciphers(inbound: Box<dyn PSOCipher + Send>, outbound: Box<dyn PSOCipher + Send>) -> PSOCodec {
+        PSOCodec {
+            framer: PacketFramer::new(inbound),
+            outbound,
+        }
+    }
+
+    // see PacketFramer::with_header
+    pub fn with_header(mut self, header: HeaderKind) -> PSOCodec {
+        self.framer = self.framer.with_header(header);
+        self
+    }
+
+    pub fn set_ciphers(&mut self, inbound: Box<dyn PSOCipher + Send>, outbound: Box<dyn PSOCipher + Send>) {
+        self.framer.set_cipher(inbound);
+        self.outbound = outbound;
+    }
+}
+
+impl Default for PSOCodec {
+    fn default() -> PSOCodec {
+        PSOCodec::new()
+    }
+}
+
+impl Decoder for PSOCodec {
+    type Item = Vec<u8>;
+    type Error = StreamError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>, StreamError> {
+        if !src.is_empty() {
+            self.framer.push(&src.split());
+        }
+        Ok(self.framer.next_packet()?)
+    }
+
+    // decode leaves src empty, so the default check for leftover bytes would never fire
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>, StreamError> {
+        match self.decode(src)? {
+            Some(pkt) => Ok(Some(pkt)),
+            None => {
+                self.framer.finish()?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Encoder<Vec<u8>> for PSOCodec {
+    type Error = StreamError;
+
+    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), StreamError> {
+        dst.extend_from_slice(&encrypt_packet(&mut self.outbound, self.framer.header(), &item)?);
+        Ok(())
+    }
+}
+
+impl Encoder<Box<dyn PSOPacket + Send>> for PSOCodec {
+    type Error = StreamError;
+
+    fn encode(&mut self, item: Box<dyn PSOPacket + Send>, dst: &mut BytesMut) -> Result<(), StreamError> {
+        self.encode(item.as_bytes(), dst)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_codec_round_trip() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+        use crate::PSOPacket;
+        use crate::crypto::pc::PSOPCCipher;
+        use crate::packet::patch::{PatchWelcome, FileInfoReply};
+        use super::PSOCodec;
+
+        let mut server = PSOCodec::new();
+        let mut client = PSOCodec::new();
+        let mut wire = BytesMut::new();
+
+        let welcome: Box<dyn PSOPacket + Send> = Box::new(PatchWelcome::new(123, 456));
+        server.encode(welcome, &mut wire).unwrap();
+        server.set_ciphers(Box::new(PSOPCCipher::new(456)), Box::new(PSOPCCipher::new(123)));
+
+        let pkt = client.decode(&mut wire).unwrap().unwrap();
+        assert!(PatchWelcome::from_bytes(&pkt).unwrap() == PatchWelcome::new(123, 456));
+        client.set_ciphers(Box::new(PSOPCCipher::new(123)), Box::new(PSOPCCipher::new(456)));
+
+        let reply = FileInfoReply {
+            id: 1,
+            checksum: 2,
+            size: 3,
+        };
+        client.encode(reply.as_bytes(), &mut wire).unwrap();
+        client.encode(reply.as_bytes(), &mut wire).unwrap();
+
+        // hand the server a partial packet first
+        let mut partial = wire.split_to(5);
+        assert!(server.decode(&mut partial).unwrap().is_none());
+        for _ in 0..2 {
+            let pkt = server.decode(&mut wire).unwrap().unwrap();
+            assert!(FileInfoReply::from_bytes(&pkt).unwrap() == reply);
+        }
+        assert!(server.decode(&mut wire).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_codec_truncated_at_eof() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+        use crate::PSOPacket;
+        use crate::net::framer::FramingError;
+        use crate::net::stream::StreamError;
+        use crate::packet::patch::FileInfoReply;
+        use super::PSOCodec;
+
+        let mut client = PSOCodec::new();
+        let mut server = PSOCodec::new();
+        let mut wire = BytesMut::new();
+        let reply = FileInfoReply {
+            id: 1,
+            checksum: 2,
+            size: 3,
+        };
+        client.encode(reply.as_bytes(), &mut wire).unwrap();
+        client.encode(reply.as_bytes(), &mut wire).unwrap();
+        wire.truncate(wire.len() - 3);
+
+        assert!(server.decode_eof(&mut wire).unwrap().is_some());
+        assert!(matches!(server.decode_eof(&mut wire), Err(StreamError::Framing(FramingError::Truncated(_)))));
+        assert!(PSOCodec::new().decode_eof(&mut BytesMut::new()).unwrap().is_none());
+    }
+}
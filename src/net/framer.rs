@@ -0,0 +1,238 @@
+use crate::HeaderKind;
+use crate::crypto::{PSOCipher, CipherError};
+
+// largest value the u16 size field can hold
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 0xFFFF;
+
+#[derive(Debug)]
+pub enum FramingError {
+    InvalidPacketSize(usize),
+    PacketTooLarge(usize),
+    Truncated(usize),
+    Cipher(CipherError),
+}
+
+impl std::fmt::Display for FramingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FramingError::InvalidPacketSize(size) => write!(f, "packet header declares an invalid size of {} bytes", size),
+            FramingError::PacketTooLarge(size) => write!(f, "packet of {} bytes is over the size limit", size),
+            FramingError::Truncated(len) => write!(f, "connection closed with {} bytes of an unfinished packet", len),
+            FramingError::Cipher(err) => write!(f, "cipher error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FramingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FramingError::Cipher(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<CipherError> for FramingError {
+    fn from(err: CipherError) -> FramingError {
+        FramingError::Cipher(err)
+    }
+}
+
+
+// takes raw bytes off the wire and hands back whole decrypted packets. the size is read from
+// wherever the header layout keeps it, which defaults to the first u16 like patch, pc and bb
+// packets. dc and gc connections need with_header.
+pub struct PacketFramer<C: PSOCipher> {
+    cipher: C,
+    header: HeaderKind,
+    buffer: Vec<u8>,
+    packet: Option<(Vec<u8>, usize)>,
+    max_size: usize,
+}
+
+impl<C: PSOCipher> PacketFramer<C> {
+    pub fn new(cipher: C) -> PacketFramer<C> {
+        PacketFramer {
+            cipher,
+            header: HeaderKind::Patch,
+            buffer: Vec::new(),
+            packet: None,
+            max_size: DEFAULT_MAX_PACKET_SIZE,
+        }
+    }
+
+    pub fn with_max_size(mut self, max_size: usize) -> PacketFramer<C> {
+        self.max_size = max_size;
+        self
+    }
+
+    // the header layout on this connection, which says where the size is when framing incoming
+    // packets. PSOStream and PSOCodec also use it to pad outgoing ones.
+    pub fn with_header(mut self, header: HeaderKind) -> PacketFramer<C> {
+        self.header = header;
+        self
+    }
+
+    pub fn header(&self) -> HeaderKind {
+        self.header
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>, FramingError> {
+        let header_size = self.cipher.header_size();
+        let block_size = self.cipher.block_size();
+
+        let (mut packet, size) = match self.packet.take() {
+            Some(packet) => packet,
+            None => {
+                if self.buffer.len() < header_size {
+                    return Ok(None);
+                }
+
+                let mut header = self.buffer.drain(..header_size).collect::<Vec<_>>();
+                self.cipher.decrypt_in_place(&mut header)?;
+
+                let offset = self.header.size_offset();
+                let size = u16::from_le_bytes([header[offset], header[offset + 1]]) as usize;
+                if size < header_size {
+                    return Err(FramingError::InvalidPacketSize(size));
+                }
+                if size > self.max_size {
+                    return Err(FramingError::PacketTooLarge(size));
+                }
+                (header, size)
+            }
+        };
+
+        let padded_size = size.div_ceil(block_size) * block_size;
+        let remaining = padded_size - packet.len();
+        if self.buffer.len() < remaining {
+            self.packet = Some((packet, size));
+            return Ok(None);
+        }
+
+        let mut body = self.buffer.drain(..remaining).collect::<Vec<_>>();
+        self.cipher.decrypt_in_place(&mut body)?;
+        packet.append(&mut body);
+        packet.truncate(size);
+        Ok(Some(packet))
+    }
+
+    // call once the connection has closed, anything still buffered is a packet that never finished
+    pub fn finish(&self) -> Result<(), FramingError> {
+        let pending = self.buffer.len() + self.packet.as_ref().map_or(0, |(packet, _)| packet.len());
+        if pending > 0 {
+            return Err(FramingError::Truncated(pending));
+        }
+        Ok(())
+    }
+
+    // takes effect from the next packet header
+    pub fn set_cipher(&mut self, cipher: C) {
+        self.cipher = cipher;
+    }
+
+    pub fn cipher(&self) -> &C {
+        &self.cipher
+    }
+
+    pub fn cipher_mut(&mut self) -> &mut C {
+        &mut self.cipher
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::PSOCipher;
+    use crate::HeaderKind;
+    use crate::crypto::pc::PSOPCCipher;
+    use crate::crypto::gc::PSOGCCipher;
+    use super::{PacketFramer, FramingError};
+
+    fn pc_packet(cmd: u8, body_len: usize) -> Vec<u8> {
+        let mut pkt = Vec::new();
+        pkt.extend_from_slice(&((body_len + 4) as u16).to_le_bytes());
+        pkt.push(cmd);
+        pkt.push(0);
+        pkt.extend((0..body_len).map(|i| i as u8));
+        pkt
+    }
+
+    #[test]
+    fn test_framer_byte_at_a_time() {
+        let mut cipher = PSOPCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOPCCipher::new(1234));
+
+        let packets = vec![pc_packet(1, 0), pc_packet(2, 12), pc_packet(3, 0x400)];
+        let mut wire = Vec::new();
+        for pkt in packets.iter() {
+            wire.append(&mut cipher.encrypt(pkt).unwrap());
+        }
+
+        let mut result = Vec::new();
+        for b in wire.iter() {
+            framer.push(&[*b]);
+            while let Some(pkt) = framer.next_packet().unwrap() {
+                result.push(pkt);
+            }
+        }
+        assert!(result == packets);
+    }
+
+    #[test]
+    fn test_framer_strips_padding() {
+        let mut cipher = PSOPCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOPCCipher::new(1234));
+
+        // declares 10 bytes, sent padded to 12
+        let mut pkt = vec![10, 0, 5, 0, 1, 2, 3, 4, 5, 6, 0, 0];
+        framer.push(&cipher.encrypt(&pkt).unwrap());
+        pkt.truncate(10);
+        assert!(framer.next_packet().unwrap() == Some(pkt));
+        assert!(framer.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_framer_errors() {
+        let mut cipher = PSOPCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOPCCipher::new(1234));
+        framer.push(&cipher.encrypt(&[0, 0, 5, 0]).unwrap());
+        assert!(matches!(framer.next_packet(), Err(FramingError::InvalidPacketSize(0))));
+
+        let mut cipher = PSOPCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOPCCipher::new(1234)).with_max_size(0x100);
+        framer.push(&cipher.encrypt(&[0, 2, 5, 0]).unwrap());
+        assert!(matches!(framer.next_packet(), Err(FramingError::PacketTooLarge(0x200))));
+
+        let mut cipher = PSOPCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOPCCipher::new(1234));
+        assert!(framer.finish().is_ok());
+        framer.push(&cipher.encrypt(&pc_packet(1, 8)).unwrap()[..6]);
+        assert!(framer.next_packet().unwrap().is_none());
+        assert!(matches!(framer.finish(), Err(FramingError::Truncated(6))));
+    }
+
+    #[test]
+    fn test_framer_gc_header() {
+        let mut cipher = PSOGCCipher::new(1234);
+        let mut framer = PacketFramer::new(PSOGCCipher::new(1234)).with_header(HeaderKind::GC);
+
+        // cmd, flag, then the size. declares 10 bytes, sent padded to 12
+        let mut first = vec![0x93, 1, 10, 0, 1, 2, 3, 4, 5, 6, 0, 0];
+        let second = vec![0x9E, 0, 8, 0, 7, 7, 7, 7];
+        let mut wire = cipher.encrypt(&first).unwrap();
+        wire.append(&mut cipher.encrypt(&second).unwrap());
+
+        framer.push(&wire[..6]);
+        assert!(framer.next_packet().unwrap().is_none());
+        framer.push(&wire[6..]);
+        first.truncate(10);
+        assert!(framer.next_packet().unwrap() == Some(first));
+        assert!(framer.next_packet().unwrap() == Some(second));
+        assert!(framer.next_packet().unwrap().is_none());
+    }
+}
@@ -0,0 +1,106 @@
+// builds the welcome packet a server opens a session with along with the ciphers for both ends.
+// the server encrypts with server_key and the client with client_key, so the server's inbound
+// cipher is the client's outbound one and vice versa.
+
+use rand::RngCore;
+
+use crate::crypto::pc::PSOPCCipher;
+use crate::crypto::bb::{PSOBBCipher, BBKeyTable};
+use crate::packet::patch::PatchWelcome;
+use crate::packet::login::LoginWelcome;
+
+
+pub fn patch_server<R: RngCore>(rng: &mut R) -> (PatchWelcome, (PSOPCCipher, PSOPCCipher)) {
+    let welcome = PatchWelcome::new(rng.next_u32(), rng.next_u32());
+    let ciphers = (PSOPCCipher::new(welcome.client_key), PSOPCCipher::new(welcome.server_key));
+    (welcome, ciphers)
+}
+
+// returns (client_in, client_out)
+pub fn patch_client(welcome: &PatchWelcome) -> (PSOPCCipher, PSOPCCipher) {
+    (PSOPCCipher::new(welcome.server_key), PSOPCCipher::new(welcome.client_key))
+}
+
+
+// pc login and ship servers send the same pair of u32 keys the patch server does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PCKeys {
+    pub server_key: u32,
+    pub client_key: u32,
+}
+
+pub fn pc_server<R: RngCore>(rng: &mut R) -> (PCKeys, (PSOPCCipher, PSOPCCipher)) {
+    let keys = PCKeys {
+        server_key: rng.next_u32(),
+        client_key: rng.next_u32(),
+    };
+    let ciphers = (PSOPCCipher::new(keys.client_key), PSOPCCipher::new(keys.server_key));
+    (keys, ciphers)
+}
+
+// returns (client_in, client_out)
+pub fn pc_client(keys: &PCKeys) -> (PSOPCCipher, PSOPCCipher) {
+    (PSOPCCipher::new(keys.server_key), PSOPCCipher::new(keys.client_key))
+}
+
+
+pub fn bb_server<R: RngCore>(rng: &mut R, key: &BBKeyTable) -> (LoginWelcome, (PSOBBCipher, PSOBBCipher)) {
+    let mut server_key = [0u8; 48];
+    let mut client_key = [0u8; 48];
+    rng.fill_bytes(&mut server_key);
+    rng.fill_bytes(&mut client_key);
+
+    let welcome = LoginWelcome::new(server_key, client_key);
+    let ciphers = (PSOBBCipher::with_key(key, client_key), PSOBBCipher::with_key(key, server_key));
+    (welcome, ciphers)
+}
+
+// returns (client_in, client_out)
+pub fn bb_client(welcome: &LoginWelcome, key: &BBKeyTable) -> (PSOBBCipher, PSOBBCipher) {
+    (PSOBBCipher::with_key(key, welcome.server_key), PSOBBCipher::with_key(key, welcome.client_key))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::crypto::PSOCipher;
+
+    fn check_directions<C: PSOCipher>(server: (C, C), client: (C, C)) {
+        let (mut server_in, mut server_out) = server;
+        let (mut client_in, mut client_out) = client;
+        let data = (0..0x40).collect::<Vec<u8>>();
+
+        assert!(client_in.decrypt(&server_out.encrypt(&data).unwrap()).unwrap() == data);
+        assert!(server_in.decrypt(&client_out.encrypt(&data).unwrap()).unwrap() == data);
+    }
+
+    #[test]
+    fn test_patch_handshake() {
+        let (welcome, server) = super::patch_server(&mut StdRng::seed_from_u64(1));
+        let (other, _) = super::patch_server(&mut StdRng::seed_from_u64(1));
+        assert!(welcome == other);
+        assert!(welcome.server_key != welcome.client_key);
+        check_directions(server, super::patch_client(&welcome));
+    }
+
+    #[test]
+    fn test_pc_handshake() {
+        let (keys, server) = super::pc_server(&mut StdRng::seed_from_u64(2));
+        check_directions(server, super::pc_client(&keys));
+    }
+
+    #[test]
+    fn test_bb_handshake() {
+        use crate::crypto::bb::{BBKeyTable, BB_KEY_FILE_SIZE};
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut key_file = vec![0u8; BB_KEY_FILE_SIZE];
+        rng.fill(&mut key_file[..]);
+        let key = BBKeyTable::from_bytes(&key_file).unwrap();
+
+        let (welcome, server) = super::bb_server(&mut rng, &key);
+        check_directions(server, super::bb_client(&welcome, &key));
+    }
+}
@@ -0,0 +1,19 @@
+use crate::HeaderKind;
+use crate::crypto::{PSOCipher, CipherError, pad_packet};
+
+pub mod framer;
+pub mod stream;
+pub mod handshake;
+#[cfg(feature = "tokio")]
+pub mod codec;
+
+
+// pads a serialized packet out to the cipher's block size and encrypts it
+pub(crate) fn encrypt_packet<C: PSOCipher + ?Sized>(cipher: &mut C, header: HeaderKind, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+    let mut buf = data.to_vec();
+    if !buf.len().is_multiple_of(cipher.block_size()) {
+        pad_packet(&mut buf, header, cipher.block_size())?;
+    }
+    cipher.encrypt_in_place(&mut buf)?;
+    Ok(buf)
+}
@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+
+use crate::{PSOPacket, HeaderKind};
+use crate::crypto::{PSOCipher, CipherError, NullCipher};
+use crate::net::encrypt_packet;
+use crate::net::framer::{PacketFramer, FramingError};
+
+const READ_BUFFER_SIZE: usize = 0x1000;
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(std::io::Error),
+    Framing(FramingError),
+    Cipher(CipherError),
+    ConnectionClosed,
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StreamError::Io(err) => write!(f, "io error: {}", err),
+            StreamError::Framing(err) => write!(f, "framing error: {}", err),
+            StreamError::Cipher(err) => write!(f, "cipher error: {}", err),
+            StreamError::ConnectionClosed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(err) => Some(err),
+            StreamError::Framing(err) => Some(err),
+            StreamError::Cipher(err) => Some(err),
+            StreamError::ConnectionClosed => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StreamError {
+    fn from(err: std::io::Error) -> StreamError {
+        StreamError::Io(err)
+    }
+}
+
+impl From<FramingError> for StreamError {
+    fn from(err: FramingError) -> StreamError {
+        StreamError::Framing(err)
+    }
+}
+
+impl From<CipherError> for StreamError {
+    fn from(err: CipherError) -> StreamError {
+        StreamError::Cipher(err)
+    }
+}
+
+
+// a blocking connection to a pso client or server. both directions start unencrypted, so the
+// welcome packet can go out before the keys are in place with set_ciphers.
+pub struct PSOStream<S: Read + Write> {
+    stream: S,
+    framer: PacketFramer<Box<dyn PSOCipher + Send>>,
+    outbound: Box<dyn PSOCipher + Send>,
+}
+
+impl<S: Read + Write> PSOStream<S> {
+    pub fn new(stream: S) -> PSOStream<S> {
+        PSOStream::with_ciphers(stream, Box::new(NullCipher {}), Box::new(NullCipher {}))
+    }
+
+    pub fn with_ciphers(stream: S, inbound: Box<dyn PSOCipher + Send>, outbound: Box<dyn PSOCipher + Send>) -> PSOStream<S> {
+        PSOStream {
+            stream,
+            framer: PacketFramer::new(inbound),
+            outbound,
+        }
+    }
+
+    // see PacketFramer::with_header
+    pub fn with_header(mut self, header: HeaderKind) -> PSOStream<S> {
+        self.framer = self.framer.with_header(header);
+        self
+    }
+
+    pub fn set_ciphers(&mut self, inbound: Box<dyn PSOCipher + Send>, outbound: Box<dyn PSOCipher + Send>) {
+        self.framer.set_cipher(inbound);
+        self.outbound = outbound;
+    }
+
+    pub fn send(&mut self, pkt: &dyn PSOPacket) -> Result<(), StreamError> {
+        self.send_raw(&pkt.as_bytes())
+    }
+
+    pub fn send_raw(&mut self, data: &[u8]) -> Result<(), StreamError> {
+        let buf = encrypt_packet(&mut self.outbound, self.framer.header(), data)?;
+        self.stream.write_all(&buf)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Result<Vec<u8>, StreamError> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        loop {
+            if let Some(pkt) = self.framer.next_packet()? {
+                return Ok(pkt);
+            }
+
+            let len = self.stream.read(&mut buf)?;
+            if len == 0 {
+                return Err(StreamError::ConnectionClosed);
+            }
+            self.framer.push(&buf[..len]);
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_patch_session() {
+        use std::net::{TcpListener, TcpStream};
+        use crate::PSOPacket;
+        use crate::crypto::pc::PSOPCCipher;
+        use crate::packet::patch::{PatchWelcome, FileInfoReply, Message};
+        use super::{PSOStream, StreamError};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut stream = PSOStream::new(socket);
+            stream.send(&PatchWelcome::new(123, 456)).unwrap();
+            stream.set_ciphers(Box::new(PSOPCCipher::new(456)), Box::new(PSOPCCipher::new(123)));
+
+            let reply = FileInfoReply::from_bytes(&stream.recv().unwrap()).unwrap();
+            stream.send(&Message::new(format!("got {}", reply.id))).unwrap();
+
+            assert!(matches!(stream.recv(), Err(StreamError::ConnectionClosed)));
+        });
+
+        let mut stream = PSOStream::new(TcpStream::connect(addr).unwrap());
+        let welcome = PatchWelcome::from_bytes(&stream.recv().unwrap()).unwrap();
+        assert!(welcome == PatchWelcome::new(123, 456));
+        stream.set_ciphers(Box::new(PSOPCCipher::new(123)), Box::new(PSOPCCipher::new(456)));
+
+        stream.send(&FileInfoReply {
+            id: 7,
+            checksum: 0xDEADBEEF,
+            size: 0x1000,
+        }).unwrap();
+        let msg = Message::from_bytes(&stream.recv().unwrap()).unwrap();
+        assert!(msg == Message::new("got 7".to_string()));
+
+        drop(stream);
+        server.join().unwrap();
+    }
+}
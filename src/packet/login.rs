@@ -1,19 +1,19 @@
 use psopacket::pso_packet;
-use crate::{PSOPacket, PacketParseError};
+use crate::{PSOPacket, PSOPacketData, PSOEnum, PacketParseError, Direction, ServerType};
 
-use std::io::{Read, Seek, SeekFrom};
+use crate::text::{FixedStr, FixedUtf16};
+
+use std::io::Read;
 
 pub const PATCH_FILE_CHUNK_SIZE: u16 = 0x8000; // 32kb
 
-#[allow(non_camel_case_types)]
-type u8_str = u8;
 
-#[pso_packet(0x03)]
+#[pso_packet(0x03, header = "bb", sent_by = "server", server = "login")]
 pub struct LoginWelcome {
     flag: u32,
-    copyright: [u8_str; 0x60],
-    server_key: [u8; 48],
-    client_key: [u8; 48],
+    copyright: FixedStr<0x60>,
+    pub server_key: [u8; 48],
+    pub client_key: [u8; 48],
 }
 
 impl LoginWelcome {
@@ -22,14 +22,14 @@ impl LoginWelcome {
         copyright[..0x4B].clone_from_slice(b"Phantasy Star Online Blue Burst Game Server. Copyright 1999-2004 SONICTEAM.");
         LoginWelcome {
             flag: 0,
-            copyright: copyright,
-            server_key: server_key,
-            client_key: client_key,
+            copyright: FixedStr::from_bytes(copyright),
+            server_key,
+            client_key,
         }
     }
 }
 
-#[pso_packet(0x93)]
+#[pso_packet(0x93, header = "bb", sent_by = "client", server = "login")]
 pub struct Login {
     pub flag: u32,
     pub tag: u32,
@@ -37,15 +37,16 @@ pub struct Login {
     pub version: u16,
     pub unknown1: [u8; 6],
     pub team: u32,
-    pub username: [u8_str; 16],
+    pub username: FixedStr<16>,
     pub unknown2: [u8; 32],
-    pub password: [u8_str; 16],
+    pub password: FixedStr<16>,
     pub unknown3: [u8; 40],
     pub hwinfo: [u8; 8],
     pub security_data: [u8; 40],
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, PSOEnum)]
+#[repr(u32)]
 pub enum AccountStatus {
     Ok,
     Error,
@@ -61,47 +62,7 @@ pub enum AccountStatus {
     BadVersion,
 }
 
-impl AccountStatus {
-    const SIZE: usize = 4;
-    
-    fn to_le_bytes(&self) -> [u8; 4] {
-        [match self {
-            AccountStatus::Ok => 0,
-            AccountStatus::Error => 1,
-            AccountStatus::InvalidPassword => 2,
-            AccountStatus::InvalidPassword2 => 3,
-            AccountStatus::Maintenance => 4,
-            AccountStatus::AlreadyOnline => 5,
-            AccountStatus::Banned => 6,
-            AccountStatus::Banned2 => 7,
-            AccountStatus::InvalidUser => 8,
-            AccountStatus::PayUp => 9,
-            AccountStatus::Locked => 10,
-            AccountStatus::BadVersion => 11,
-        },0,0,0]
-    }
-
-    fn from_le_bytes(bytes: [u8; 4]) -> Result<AccountStatus, PacketParseError> {
-        match bytes[0] {
-            0 => Ok(AccountStatus::Ok),
-            1 => Ok(AccountStatus::Error),
-            2 => Ok(AccountStatus::InvalidPassword),
-            3 => Ok(AccountStatus::InvalidPassword2),
-            4 => Ok(AccountStatus::Maintenance),
-            5 => Ok(AccountStatus::AlreadyOnline),
-            6 => Ok(AccountStatus::Banned),
-            7 => Ok(AccountStatus::Banned2),
-            8 => Ok(AccountStatus::InvalidUser),
-            9 => Ok(AccountStatus::PayUp),
-            10 => Ok(AccountStatus::Locked),
-            11 => Ok(AccountStatus::BadVersion),
-            _ => Err(PacketParseError::InvalidValue),
-        }
-    }
-        
-}
-
-#[pso_packet(0xE6)]
+#[pso_packet(0xE6, header = "bb", sent_by = "server", server = "login")]
 pub struct LoginResponse {
     pub flag: u32,
     pub status: AccountStatus,
@@ -116,24 +77,24 @@ impl LoginResponse {
     pub fn by_status(status: AccountStatus, security_data: [u8; 40]) -> LoginResponse {
         LoginResponse {
             flag: 0,
-            status: status,
+            status,
             tag: 0x00010000,
             //tag: 0x00000100,
             guildcard: 0,
             team_id: 0,
-            security_data: security_data,
+            security_data,
             caps: 0x00000102,
         }
     }
 }
 
 
-#[pso_packet(0xE0)]
+#[pso_packet(0xE0, header = "bb", sent_by = "client", server = "login")]
 pub struct RequestSettings {
     pub flag: u32
 }
 
-#[pso_packet(0xE2)]
+#[pso_packet(0xE2, header = "bb", sent_by = "server", server = "login")]
 pub struct SendKeyAndTeamSettings {
     flag: u32,
     unknown: [u8; 0x114],
@@ -141,12 +102,10 @@ pub struct SendKeyAndTeamSettings {
     joystick_config: [u8; 0x38],
     guildcard: u32,
     team_id: u32,
-    //team_info: [u32; 2],
-    team_info: [u8; 8],
+    team_info: [u32; 2],
     team_priv: u16,
     unknown2: u16,
-    //team_name: [u16; 16],
-    team_name: [u8; 32],
+    team_name: FixedUtf16<16>,
     team_flag: [u8; 2048],
     team_rewards: [u8; 8],
 }
@@ -156,23 +115,112 @@ impl SendKeyAndTeamSettings {
         SendKeyAndTeamSettings {
             flag: 0,
             unknown: [0; 0x114],
-            key_config: key_config,
-            joystick_config: joystick_config,
-            guildcard: guildcard,
-            team_id: team_id,
-            //team_info: [0; 2],
-            team_info: [0; 8],
+            key_config,
+            joystick_config,
+            guildcard,
+            team_id,
+            team_info: [0; 2],
             team_priv: 0,
             unknown2: 0,
-            //team_name: [0; 16],
-            team_name: [0; 32],
+            team_name: FixedUtf16::default(),
             team_flag: [0; 2048],
             team_rewards: [0; 8]
         }
     }
 }
 
-#[pso_packet(0x19)]
+// SendKeyAndTeamSettings read in place. nothing is copied until a field is asked for, the
+// offsets are from the start of the packet
+#[derive(Debug, Clone, Copy)]
+pub struct SendKeyAndTeamSettingsView<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SendKeyAndTeamSettingsView<'a> {
+    const KEY_CONFIG: usize = 0x11C;
+    const JOYSTICK_CONFIG: usize = 0x288;
+    const GUILDCARD: usize = 0x2C0;
+    const TEAM_ID: usize = 0x2C4;
+    const TEAM_NAME: usize = 0x2D4;
+    const TEAM_FLAG: usize = 0x2F4;
+    const SIZE: usize = 0xAFC;
+
+    pub fn from_bytes(data: &'a [u8]) -> Result<SendKeyAndTeamSettingsView<'a>, PacketParseError> {
+        if data.len() < 8 {
+            return Err(PacketParseError::NotEnoughBytes);
+        }
+        let len = u16::from_le_bytes([data[0], data[1]]);
+        let cmd = u16::from_le_bytes([data[2], data[3]]);
+        if cmd != 0xE2 {
+            return Err(PacketParseError::WrongPacketCommand {
+                packet: "SendKeyAndTeamSettings",
+                expected: 0xE2,
+                got: cmd,
+            });
+        }
+        if len as usize != data.len() {
+            return Err(PacketParseError::WrongPacketSize {
+                packet: "SendKeyAndTeamSettings",
+                cmd,
+                declared: len,
+                len: data.len(),
+            });
+        }
+        if data.len() != SendKeyAndTeamSettingsView::SIZE && data.len() != SendKeyAndTeamSettingsView::SIZE.div_ceil(8) * 8 {
+            return Err(PacketParseError::NotEnoughBytes);
+        }
+        Ok(SendKeyAndTeamSettingsView {
+            data,
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([self.data[offset], self.data[offset + 1], self.data[offset + 2], self.data[offset + 3]])
+    }
+
+    pub fn flag(&self) -> u32 {
+        self.u32_at(4)
+    }
+
+    pub fn key_config(&self) -> &'a [u8] {
+        &self.data[SendKeyAndTeamSettingsView::KEY_CONFIG..SendKeyAndTeamSettingsView::JOYSTICK_CONFIG]
+    }
+
+    pub fn joystick_config(&self) -> &'a [u8] {
+        &self.data[SendKeyAndTeamSettingsView::JOYSTICK_CONFIG..SendKeyAndTeamSettingsView::GUILDCARD]
+    }
+
+    pub fn guildcard(&self) -> u32 {
+        self.u32_at(SendKeyAndTeamSettingsView::GUILDCARD)
+    }
+
+    pub fn team_id(&self) -> u32 {
+        self.u32_at(SendKeyAndTeamSettingsView::TEAM_ID)
+    }
+
+    pub fn team_name(&self) -> FixedUtf16<16> {
+        let mut units = [0u16; 16];
+        let name = &self.data[SendKeyAndTeamSettingsView::TEAM_NAME..SendKeyAndTeamSettingsView::TEAM_FLAG];
+        for (dst, c) in units.iter_mut().zip(name.chunks_exact(2)) {
+            *dst = u16::from_le_bytes([c[0], c[1]]);
+        }
+        FixedUtf16::from_units(units)
+    }
+
+    pub fn team_flag(&self) -> &'a [u8] {
+        &self.data[SendKeyAndTeamSettingsView::TEAM_FLAG..SendKeyAndTeamSettingsView::TEAM_FLAG + 2048]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn to_packet(&self) -> Result<SendKeyAndTeamSettings, PacketParseError> {
+        SendKeyAndTeamSettings::from_bytes(self.data)
+    }
+}
+
+#[pso_packet(0x19, header = "bb", sent_by = "server", server = "login")]
 pub struct RedirectClient {
     pub flag: u32,
     pub ip: u32,
@@ -184,19 +232,19 @@ impl RedirectClient {
     pub fn new(ip: u32, port: u16) -> RedirectClient {
         RedirectClient {
             flag: 0,
-            ip: ip,
-            port: port,
+            ip,
+            port,
             padding: 0,
         }
     }
 }
 
-#[pso_packet(0x1E8)]
+#[pso_packet(0x1E8, header = "bb", sent_by = "client", server = "login")]
 pub struct Checksum {
     pub flag: u32,
 }
 
-#[pso_packet(0x2E8)]
+#[pso_packet(0x2E8, header = "bb", sent_by = "server", server = "login")]
 pub struct ChecksumAck {
     pub flag: u32,
     pub ack: u32,
@@ -206,11 +254,26 @@ impl ChecksumAck {
     pub fn new(ack: u32) -> ChecksumAck {
         ChecksumAck {
             flag: 0,
-            ack: ack,
+            ack,
         }
     }
 }
 
+packet_enum!(ClientPacket, crate::packet::bb_header, ServerType::Login, Direction::ClientToServer, {
+    Login(Login),
+    RequestSettings(RequestSettings),
+    Checksum(Checksum),
+});
+
+packet_enum!(ServerPacket, crate::packet::bb_header, ServerType::Login, Direction::ServerToClient, {
+    LoginWelcome(LoginWelcome),
+    RedirectClient(RedirectClient),
+    SendKeyAndTeamSettings(SendKeyAndTeamSettings),
+    LoginResponse(LoginResponse),
+    ChecksumAck(ChecksumAck),
+});
+
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -238,7 +301,7 @@ mod tests {
     #[test]
     fn test_key_settings_reply() {
         use super::PSOPacket;
-        use rand::{Rng, RngCore};
+        use rand::Rng;
 
         let mut rng = rand::thread_rng();
 
@@ -255,10 +318,138 @@ mod tests {
         assert!(bytes[8 + 0x114 + 0x16C] == joystick_config[0]);
     }
 
+    #[test]
+    fn test_key_settings_view() {
+        use super::PSOPacket;
+
+        let mut pkt = super::SendKeyAndTeamSettings::new([1; 0x16C], [2; 0x38], 123, 456);
+        pkt.flag = 7;
+        pkt.team_name = crate::text::FixedUtf16::new("team").unwrap();
+        pkt.team_flag = [3; 2048];
+        let bytes = pkt.as_bytes();
+
+        let view = super::SendKeyAndTeamSettingsView::from_bytes(&bytes).unwrap();
+        assert!(view.flag() == 7);
+        assert!(view.key_config() == &[1; 0x16C][..]);
+        assert!(view.joystick_config() == &[2; 0x38][..]);
+        assert!(view.guildcard() == 123);
+        assert!(view.team_id() == 456);
+        assert!(view.team_name() == pkt.team_name);
+        assert!(view.team_flag() == &[3; 2048][..]);
+        assert!(view.to_packet().unwrap() == pkt);
+
+        // the packet sitting in a larger receive buffer
+        let mut recv = bytes.clone();
+        recv.extend_from_slice(&[0xFF; 32]);
+        assert!(super::SendKeyAndTeamSettingsView::from_bytes(&recv[..bytes.len()]).unwrap().guildcard() == 123);
+        assert!(super::SendKeyAndTeamSettingsView::from_bytes(&bytes[..bytes.len() - 8]).is_err());
+    }
+
+    #[test]
+    fn test_encode_into() {
+        use super::PSOPacket;
+
+        let pkt = super::ChecksumAck::new(1);
+        let mut buf = vec![0xAA; 3];
+        pkt.encode_into(&mut buf);
+        assert!(buf[..3] == [0xAA; 3]);
+        assert!(buf[3..] == pkt.as_bytes()[..]);
+
+        let mut out = Vec::new();
+        pkt.write_to(&mut out).unwrap();
+        assert!(out == pkt.as_bytes());
+        pkt.write_to_with_buf(&mut out, &mut buf).unwrap();
+        assert!(buf == pkt.as_bytes());
+        assert!(out == [pkt.as_bytes(), pkt.as_bytes()].concat());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        use super::{PSOPacket, ClientPacket};
+        use crate::{PacketParseError, ServerType};
+
+        let checksum = super::Checksum {
+            flag: 0x12345678,
+        };
+        match ClientPacket::parse(&checksum.as_bytes()) {
+            Ok(ClientPacket::Checksum(pkt)) => assert!(pkt == checksum),
+            other => panic!("parsed {:?}", other),
+        }
+
+        let unknown = vec![8, 0, 0x05, 0, 9, 0, 0, 0];
+        assert!(ClientPacket::parse(&unknown) == Ok(ClientPacket::Unknown {
+            cmd: 0x05,
+            flag: 9,
+            data: unknown.clone(),
+        }));
+        assert!(ClientPacket::parse(&unknown).unwrap().as_bytes() == unknown);
+
+        // the patch server's login, which shares its command with RequestLogin going the other way
+        let mut patch_login = vec![0x70, 0, 0x04, 0];
+        patch_login.resize(0x70, 0);
+        assert!(ClientPacket::parse(&patch_login) == Err(PacketParseError::WrongPacketForServerType {
+            packet: "LoginReply",
+            server: ServerType::Patch,
+        }));
+    }
+
     #[test]
     fn test_login_checksum_ack() {
         use super::PSOPacket;
         let pkt = super::ChecksumAck::new(1);
-        assert!(pkt.as_bytes() == [0xC, 0, 0xE8, 0x02, 0,0,0,0, 1,0,0,0]);
+        assert!(pkt.as_bytes() == [0x10, 0, 0xE8, 0x02, 0,0,0,0, 1,0,0,0, 0,0,0,0]);
+        assert!(super::ChecksumAck::from_bytes(&pkt.as_bytes()).unwrap() == pkt);
+    }
+
+    #[test]
+    fn test_bb_header_flag() {
+        use super::PSOPacket;
+        let pkt = super::Checksum {
+            flag: 0x12345678,
+        };
+        assert!(pkt.as_bytes() == [8, 0, 0xE8, 0x01, 0x78, 0x56, 0x34, 0x12]);
+        assert!(super::Checksum::from_bytes(&pkt.as_bytes()).unwrap() == pkt);
+    }
+
+    #[test]
+    fn test_field_errors() {
+        use super::PSOPacket;
+        use crate::PacketParseError;
+        use crate::text::FixedStr;
+
+        let login = super::Login {
+            flag: 0,
+            tag: 0x00010000,
+            guildcard: 0,
+            version: 0x41,
+            unknown1: [0; 6],
+            team: 0,
+            username: FixedStr::default(),
+            unknown2: [0; 32],
+            password: FixedStr::default(),
+            unknown3: [0; 40],
+            hwinfo: [0; 8],
+            security_data: [0; 40],
+        };
+        let mut bytes = login.as_bytes();
+        bytes.truncate(0x20);
+        bytes[0] = 0x20;
+        let err = super::Login::from_bytes(&bytes).unwrap_err();
+        assert!(err == PacketParseError::Field {
+            packet: "Login",
+            field: "username",
+            offset: 0x1C,
+            error: Box::new(PacketParseError::NotEnoughBytes),
+        });
+        assert!(err.to_string() == "Login.username at offset 0x1C: not enough bytes");
+
+        let mut bytes = super::LoginResponse::by_status(super::AccountStatus::Ok, [0; 40]).as_bytes();
+        bytes[8] = 0xFF;
+        assert!(super::LoginResponse::from_bytes(&bytes).unwrap_err() == PacketParseError::Field {
+            packet: "LoginResponse",
+            field: "status",
+            offset: 8,
+            error: Box::new(PacketParseError::InvalidValue),
+        });
     }
 }
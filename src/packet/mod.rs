@@ -1,2 +1,134 @@
+use crate::PacketParseError;
+
+// cmd and flag out of the first bytes of a packet, enough to pick which struct parses the rest
+pub(crate) fn patch_header(data: &[u8]) -> Result<(u16, u32), PacketParseError> {
+    match data.get(..4) {
+        Some(h) => Ok((u16::from_le_bytes([h[2], h[3]]), 0)),
+        None => Err(PacketParseError::NotEnoughBytes),
+    }
+}
+
+pub(crate) fn bb_header(data: &[u8]) -> Result<(u16, u32), PacketParseError> {
+    match data.get(..8) {
+        Some(h) => Ok((u16::from_le_bytes([h[2], h[3]]), u32::from_le_bytes([h[4], h[5], h[6], h[7]]))),
+        None => Err(PacketParseError::NotEnoughBytes),
+    }
+}
+
+// an enum over every packet one side of a connection sends, so a whole buffer can be parsed and
+// matched on in one go. a command that belongs to another server is WrongPacketForServerType,
+// anything else without a packet here comes out as Unknown.
+macro_rules! packet_enum {
+    ($name:ident, $header:path, $server:expr, $direction:expr, { $($variant:ident($packet:ty),)* }) => {
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum $name {
+            $($variant($packet),)*
+            Unknown {
+                cmd: u16,
+                flag: u32,
+                data: Vec<u8>,
+            },
+        }
+
+        impl $name {
+            pub const SERVER: $crate::ServerType = $server;
+            pub const DIRECTION: $crate::Direction = $direction;
+
+            pub fn parse(data: &[u8]) -> Result<$name, $crate::PacketParseError> {
+                let (cmd, flag) = $header(data)?;
+                match cmd {
+                    $(<$packet>::CMD => Ok($name::$variant(<$packet as $crate::PSOPacket>::from_bytes(data)?)),)*
+                    _ => {
+                        let other_server = $crate::packets()
+                            .filter(|info| info.cmd == cmd)
+                            .filter(|info| info.direction == $name::DIRECTION || info.direction == $crate::Direction::Both)
+                            .find_map(|info| match info.server {
+                                Some(server) if server != $name::SERVER => Some((info.name, server)),
+                                _ => None,
+                            });
+                        if let Some((packet, server)) = other_server {
+                            return Err($crate::PacketParseError::WrongPacketForServerType {
+                                packet,
+                                server,
+                            });
+                        }
+                        Ok($name::Unknown {
+                            cmd,
+                            flag,
+                            data: data.to_vec(),
+                        })
+                    },
+                }
+            }
+
+            pub fn cmd(&self) -> u16 {
+                match self {
+                    $($name::$variant(_) => <$packet>::CMD,)*
+                    $name::Unknown { cmd, .. } => *cmd,
+                }
+            }
+        }
+
+        impl $crate::PSOPacket for $name {
+            fn from_bytes(data: &[u8]) -> Result<$name, $crate::PacketParseError> {
+                $name::parse(data)
+            }
+
+            fn encode_into(&self, buf: &mut Vec<u8>) {
+                match self {
+                    $($name::$variant(pkt) => pkt.encode_into(buf),)*
+                    $name::Unknown { data, .. } => buf.extend_from_slice(data),
+                }
+            }
+        }
+
+        $(
+            impl From<$packet> for $name {
+                fn from(pkt: $packet) -> $name {
+                    $name::$variant(pkt)
+                }
+            }
+        )*
+    }
+}
+
 pub mod login;
 pub mod patch;
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_packet_registry() {
+        use crate::{PacketInfo, HeaderKind, Direction, ServerType};
+        use super::{login, patch};
+
+        assert!(login::Login::NAME == "Login");
+        assert!(login::Login::HEADER == HeaderKind::BB);
+        assert!(login::Login::INFO == PacketInfo {
+            cmd: 0x93,
+            name: "Login",
+            header: HeaderKind::BB,
+            direction: Direction::ClientToServer,
+            server: Some(ServerType::Login),
+        });
+        assert!(patch::FileSend::INFO.name == "FileSend");
+
+        let packets = crate::packets().collect::<Vec<_>>();
+        for info in [login::Login::INFO, login::LoginResponse::INFO, login::RedirectClient::INFO,
+                     patch::PatchWelcome::INFO, patch::FileSend::INFO, patch::FileInfoReply::INFO,
+                     patch::RedirectClient::INFO].iter() {
+            assert!(packets.contains(&info));
+        }
+
+        // each packet is registered once, and a command only means one thing for a given server
+        // and direction
+        for a in packets.iter() {
+            assert!(packets.iter().filter(|b| b == &a).count() == 1);
+        }
+        for a in packets.iter().filter(|info| info.server.is_some()) {
+            assert!(packets.iter().filter(|b| (b.cmd, b.server, b.direction) == (a.cmd, a.server, a.direction)).count() == 1);
+        }
+    }
+}
@@ -1,73 +1,68 @@
 use psopacket::pso_packet;
-use crate::{PSOPacket, PacketParseError};
+use crate::{PSOPacket, PSOPacketData, PacketParseError, HeaderKind, Direction, ServerType};
+use crate::text::{FixedStr, TextError};
 
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Read;
 
 pub const PATCH_FILE_CHUNK_SIZE: u16 = 0x8000; // 32kb
 
-#[allow(non_camel_case_types)]
-type u8_str = u8;
-
 // outgoing packets
-#[pso_packet(0x02)]
+#[pso_packet(0x02, sent_by = "server", server = "patch")]
 pub struct PatchWelcome {
-    copyright: [u8_str; 44],
+    copyright: [u8; 44],
     padding: [u8; 20],
-    server_key: u32,
-    client_key: u32,
+    pub server_key: u32,
+    pub client_key: u32,
 }
 
 impl PatchWelcome {
     pub fn new(server_key: u32, client_key: u32) -> PatchWelcome {
         PatchWelcome {
-            copyright: b"Patch Server. Copyright SonicTeam, LTD. 2001".clone(),
+            copyright: *b"Patch Server. Copyright SonicTeam, LTD. 2001",
             padding: [0; 20],
-            server_key: server_key,
-            client_key: client_key,
+            server_key,
+            client_key,
         }
     }
 }
 
 
 // incoming packets
-#[pso_packet(0x02)]
+#[pso_packet(0x02, sent_by = "client", server = "patch")]
 pub struct PatchWelcomeReply {
 }
 
-#[pso_packet(0x04)]
+#[pso_packet(0x04, sent_by = "server", server = "patch")]
 pub struct RequestLogin {
 }
 
-#[pso_packet(0x04)]
+#[pso_packet(0x04, sent_by = "client", server = "patch")]
 pub struct LoginReply {
     unused: [u8; 12],
-    username: [u8_str; 16],
-    password: [u8_str; 16],
+    username: FixedStr<16>,
+    password: FixedStr<16>,
     unused2: [u8; 64],
 }
 
-#[pso_packet(0x06)]
+#[pso_packet(0x06, sent_by = "server", server = "patch")]
 pub struct StartFileSend {
     id: u32,
     size: u32,
-    filename: [u8_str; 48],
+    filename: FixedStr<48>,
 }
 
 impl StartFileSend {
-    pub fn new(filename: &str, size: u32, id: u32) -> StartFileSend {
-        let mut f = [0u8; 48];
-        for (src, dst) in filename.as_bytes().iter().zip(f.iter_mut()) {
-            *dst = *src
-        }
-        StartFileSend {
-            id: id,
-            size: size,
-            filename: f,
-        }
+    pub fn new(filename: &str, size: u32, id: u32) -> Result<StartFileSend, TextError> {
+        Ok(StartFileSend {
+            id,
+            size,
+            filename: FixedStr::new(filename)?,
+        })
     }
 }
 
 //#[pso_packet(0x07)]
+#[derive(Clone, PartialEq)]
 pub struct FileSend {
     pub chunk_num: u32,
     pub checksum: u32,
@@ -75,50 +70,120 @@ pub struct FileSend {
     pub buffer: [u8; PATCH_FILE_CHUNK_SIZE as usize],
 }
 
+crate::packet_info!(FileSend, 0x07, HeaderKind::Patch, Direction::ServerToClient, Some(ServerType::Patch));
+
 impl PSOPacket for FileSend {
-    fn from_bytes(_data: &Vec<u8>) -> Result<FileSend, PacketParseError> {
-        // TODO: implement this? it shouldn't be called on the server side ever...
-        unimplemented!();
+    fn from_bytes(data: &[u8]) -> Result<FileSend, PacketParseError> {
+        let view = FileSendView::from_bytes(data)?;
+        let mut buffer = [0u8; PATCH_FILE_CHUNK_SIZE as usize];
+        buffer[..view.chunk.len()].copy_from_slice(view.chunk);
+        Ok(FileSend {
+            chunk_num: view.chunk_num,
+            checksum: view.checksum,
+            chunk_size: view.chunk.len() as u32,
+            buffer,
+        })
     }
 
-    fn as_bytes(&self) -> Vec<u8> {
-        let mut buf: Vec<u8> = Vec::new();
-        buf.extend_from_slice(&u32::to_le_bytes(self.chunk_num));
-        buf.extend_from_slice(&u32::to_le_bytes(self.checksum));
-        buf.extend_from_slice(&u32::to_le_bytes(self.chunk_size));
-        buf.extend_from_slice(&self.buffer[0..self.chunk_size as usize]);
-        while buf.len() % 4 != 0 {
-            buf.push(0);
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        FileSendView {
+            chunk_num: self.chunk_num,
+            checksum: self.checksum,
+            chunk: &self.buffer[..(self.chunk_size as usize).min(self.buffer.len())],
+        }.encode_into(buf)
+    }
+}
+
+// FileSend without the copy into a 32kb array, the chunk stays in the buffer it was read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileSendView<'a> {
+    pub chunk_num: u32,
+    pub checksum: u32,
+    pub chunk: &'a [u8],
+}
+
+impl<'a> FileSendView<'a> {
+    pub fn from_bytes(data: &'a [u8]) -> Result<FileSendView<'a>, PacketParseError> {
+        if data.len() < 16 {
+            return Err(PacketParseError::NotEnoughBytes);
+        }
+        let len = u16::from_le_bytes([data[0], data[1]]);
+        let cmd = u16::from_le_bytes([data[2], data[3]]);
+        if cmd != 0x07 {
+            return Err(PacketParseError::WrongPacketCommand {
+                packet: "FileSend",
+                expected: 0x07,
+                got: cmd,
+            });
+        }
+        if len as usize != data.len() {
+            return Err(PacketParseError::WrongPacketSize {
+                packet: "FileSend",
+                cmd,
+                declared: len,
+                len: data.len(),
+            });
         }
-        //buf
 
-        //buf.extend_from_slice(&u16::to_le_bytes((4 * 4) as u16 + self.chunk_size as u16));
-        //buf.extend_from_slice(&u16::to_le_bytes(0x07));
-        let pkt_len = (buf.len() + 4) as u16;
-        let mut prebuf: Vec<u8> = Vec::new();
+        let chunk_num = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        let checksum = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+        let chunk_size = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+        if chunk_size > PATCH_FILE_CHUNK_SIZE as u32 {
+            return Err(PacketParseError::Field {
+                packet: "FileSend",
+                field: "chunk_size",
+                offset: 12,
+                error: Box::new(PacketParseError::InvalidValue),
+            });
+        }
 
-        prebuf.extend_from_slice(&u16::to_le_bytes(pkt_len));
-        prebuf.extend_from_slice(&u16::to_le_bytes(0x07));
-        prebuf.append(&mut buf);
+        let chunk = match data.get(16..16 + chunk_size as usize) {
+            Some(chunk) => chunk,
+            None => return Err(PacketParseError::NotEnoughBytes),
+        };
+        // the chunk is padded out to 4 bytes
+        if (16 + chunk.len()).div_ceil(4) * 4 != data.len() {
+            return Err(PacketParseError::DataStructNotLargeEnough {
+                packet: "FileSend",
+                cmd,
+                position: 16 + chunk.len() as u64,
+                len: data.len(),
+            });
+        }
 
-        prebuf
+        Ok(FileSendView {
+            chunk_num,
+            checksum,
+            chunk,
+        })
+    }
 
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        let pkt_len = (16 + self.chunk.len()).div_ceil(4) * 4;
+        buf.reserve(pkt_len);
+        buf.extend_from_slice(&u16::to_le_bytes(pkt_len as u16));
+        buf.extend_from_slice(&u16::to_le_bytes(0x07));
+        buf.extend_from_slice(&u32::to_le_bytes(self.chunk_num));
+        buf.extend_from_slice(&u32::to_le_bytes(self.checksum));
+        buf.extend_from_slice(&u32::to_le_bytes(self.chunk.len() as u32));
+        buf.extend_from_slice(self.chunk);
+        buf.resize(buf.len() + pkt_len - 16 - self.chunk.len(), 0);
     }
 }
 
 impl std::fmt::Debug for FileSend {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "packet FileSend {{\n").unwrap();
-        write!(f, "    chunk_num: {:?}\n", self.chunk_num).unwrap();
-        write!(f, "    checksum: {:X?}\n", self.checksum).unwrap();
-        write!(f, "    chunk_size: {:X?}\n", self.chunk_size).unwrap();
-        write!(f, "    buffer: [...a large array ...]\n").unwrap();
+        writeln!(f, "packet FileSend {{").unwrap();
+        writeln!(f, "    chunk_num: {:?}", self.chunk_num).unwrap();
+        writeln!(f, "    checksum: {:X?}", self.checksum).unwrap();
+        writeln!(f, "    chunk_size: {:X?}", self.chunk_size).unwrap();
+        writeln!(f, "    buffer: [...a large array ...]").unwrap();
         write!(f, "}}")
     }
 }
 
 
-#[pso_packet(0x08)]
+#[pso_packet(0x08, sent_by = "server", server = "patch")]
 pub struct EndFileSend {
     padding: u32,
 }
@@ -131,69 +196,67 @@ impl EndFileSend {
     }
 }
 
+impl Default for EndFileSend {
+    fn default() -> EndFileSend {
+        EndFileSend::new()
+    }
+}
+
 
 
-#[pso_packet(0x0B)]
+#[pso_packet(0x0B, sent_by = "server", server = "patch")]
 pub struct PatchStartList {
 }
 
-#[pso_packet(0x09)]
+#[pso_packet(0x09, sent_by = "server", server = "patch")]
 pub struct ChangeDirectory {
-    dirname: [u8_str; 64]
+    dirname: FixedStr<64>,
 }
 
 impl ChangeDirectory {
-    pub fn new(dirname: &str) -> ChangeDirectory {
-        let mut d = [0u8; 64];
-        for (src, dst) in dirname.as_bytes().iter().zip(d.iter_mut()) {
-            *dst = *src
-        }
-        ChangeDirectory {
-            dirname: d,
-        }
+    pub fn new(dirname: &str) -> Result<ChangeDirectory, TextError> {
+        Ok(ChangeDirectory {
+            dirname: FixedStr::new(dirname)?,
+        })
     }
 }
 
-#[pso_packet(0x0A)]
+#[pso_packet(0x0A, sent_by = "server", server = "patch")]
 pub struct UpOneDirectory {
 }
 
-#[pso_packet(0x0C)]
+#[pso_packet(0x0C, sent_by = "server", server = "patch")]
 pub struct FileInfo {
     id: u32,
-    filename: [u8_str; 32],
+    filename: FixedStr<32>,
 }
 
 impl FileInfo {
-    pub fn new(filename: &str, id: u32) -> FileInfo {
-        let mut f = [0u8; 32];
-        for (src, dst) in filename.as_bytes().iter().zip(f.iter_mut()) {
-            *dst = *src
-        };
-        FileInfo {
-            id: id,
-            filename: f,
-        }
+    pub fn new(filename: &str, id: u32) -> Result<FileInfo, TextError> {
+        Ok(FileInfo {
+            id,
+            filename: FixedStr::new(filename)?,
+        })
     }
 }
 
 
-#[pso_packet(0x0D)]
+#[pso_packet(0x0D, sent_by = "server", server = "patch")]
 pub struct PatchEndList {
 }
 
-#[pso_packet(0x0F)]
+#[pso_packet(0x0F, sent_by = "client", server = "patch")]
 pub struct FileInfoReply {
     pub id: u32,
     pub checksum: u32,
     pub size: u32,
 }
 
-#[pso_packet(0x10)]
+#[pso_packet(0x10, sent_by = "client", server = "patch")]
 pub struct FileInfoListEnd {
 }
 
-#[pso_packet(0x11)]
+#[pso_packet(0x11, sent_by = "server", server = "patch")]
 pub struct FilesToPatchMetadata {
     data_size: u32,
     file_count: u32,
@@ -202,19 +265,19 @@ pub struct FilesToPatchMetadata {
 impl FilesToPatchMetadata {
     pub fn new(data_size: u32, file_count: u32) -> FilesToPatchMetadata {
         FilesToPatchMetadata {
-            data_size: data_size,
-            file_count: file_count,
+            data_size,
+            file_count,
         }
     }
 }
 
 
-#[pso_packet(0x12)]
+#[pso_packet(0x12, sent_by = "server", server = "patch")]
 pub struct FinalizePatching {
 }
 
 
-#[pso_packet(0x13)]
+#[pso_packet(0x13, sent_by = "server", server = "patch")]
 pub struct Message {
     msg: String,
 }
@@ -223,13 +286,13 @@ impl Message {
     pub fn new(mut msg: String) -> Message {
         msg.push('\0');
         Message {
-            msg: msg,
+            msg,
         }
     }
 }
 
 
-#[pso_packet(0x14)]
+#[pso_packet(0x14, sent_by = "server", server = "patch")]
 pub struct RedirectClient {
     ip: u32,
     port: u16,
@@ -239,14 +302,39 @@ pub struct RedirectClient {
 impl RedirectClient {
     pub fn new(ip: u32, port: u16) -> RedirectClient {
         RedirectClient {
-            ip: ip,
-            port: port,
+            ip,
+            port,
             padding: 0,
         }
     }
 }
 
 
+packet_enum!(ClientPacket, crate::packet::patch_header, ServerType::Patch, Direction::ClientToServer, {
+    PatchWelcomeReply(PatchWelcomeReply),
+    LoginReply(LoginReply),
+    FileInfoReply(FileInfoReply),
+    FileInfoListEnd(FileInfoListEnd),
+});
+
+packet_enum!(ServerPacket, crate::packet::patch_header, ServerType::Patch, Direction::ServerToClient, {
+    PatchWelcome(PatchWelcome),
+    RequestLogin(RequestLogin),
+    StartFileSend(StartFileSend),
+    FileSend(FileSend),
+    EndFileSend(EndFileSend),
+    ChangeDirectory(ChangeDirectory),
+    UpOneDirectory(UpOneDirectory),
+    PatchStartList(PatchStartList),
+    FileInfo(FileInfo),
+    PatchEndList(PatchEndList),
+    FilesToPatchMetadata(FilesToPatchMetadata),
+    FinalizePatching(FinalizePatching),
+    Message(Message),
+    RedirectClient(RedirectClient),
+});
+
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -269,7 +357,7 @@ mod tests {
         let new_pkt = super::PatchWelcome::from_bytes(&bytes);
 
         assert!(new_pkt == Ok(super::PatchWelcome {
-            copyright: b"Patch Server. Copyright Elsewhere, LTD. 2001".clone(),
+            copyright: *b"Patch Server. Copyright Elsewhere, LTD. 2001",
             padding: [0; 20],
             server_key: 123,
             client_key: 456,
@@ -306,4 +394,112 @@ mod tests {
                           0x6F, 0x00, 0x66, 0x00, 0x20, 0x00, 0x6C, 0x00, 0x65, 0x00, 0x74, 0x00, 0x74, 0x00, 0x65, 0x00,
                           0x72, 0x00, 0x73, 0x00, 0x21, 0x00, 0x00, 0x00])
     }
+
+    #[test]
+    fn test_file_info() {
+        use super::PSOPacket;
+        use crate::text::TextError;
+
+        let info = super::FileInfo::new("data.gsl", 3).unwrap();
+        let bytes = info.as_bytes();
+        assert!(bytes[..8] == [0x28, 0x00, 0x0C, 0x00, 3, 0, 0, 0]);
+        assert!(bytes[8..] == *b"data.gsl\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        assert!(super::FileInfo::from_bytes(&bytes).unwrap().filename.as_str() == Ok("data.gsl"));
+
+        let name = "x".repeat(32);
+        assert!(super::FileInfo::new(&name, 3).unwrap_err() == TextError::TooLong { max: 31, len: 32 });
+    }
+
+    #[test]
+    fn test_file_send() {
+        use super::{PSOPacket, PATCH_FILE_CHUNK_SIZE};
+        use crate::PacketParseError;
+
+        let mut buffer = [0u8; PATCH_FILE_CHUNK_SIZE as usize];
+        buffer[..5].copy_from_slice(b"chunk");
+        let pkt = super::FileSend {
+            chunk_num: 2,
+            checksum: 0x12345678,
+            chunk_size: 5,
+            buffer,
+        };
+        let bytes = pkt.as_bytes();
+        assert!(bytes.len() == 24);
+
+        let parsed = super::FileSend::from_bytes(&bytes).unwrap();
+        assert!(parsed.chunk_num == 2 && parsed.checksum == 0x12345678 && parsed.chunk_size == 5);
+        assert!(parsed.buffer[..] == buffer[..]);
+
+        let view = super::FileSendView::from_bytes(&bytes).unwrap();
+        assert!(view.chunk == b"chunk");
+        let mut encoded = Vec::new();
+        view.encode_into(&mut encoded);
+        assert!(encoded == bytes);
+
+        let mut bytes = bytes;
+        bytes[12..16].copy_from_slice(&0x8001u32.to_le_bytes());
+        assert!(matches!(super::FileSend::from_bytes(&bytes), Err(PacketParseError::Field { field: "chunk_size", .. })));
+    }
+
+    #[test]
+    fn test_dispatch() {
+        use super::{PSOPacket, ClientPacket, ServerPacket};
+        use crate::{PacketParseError, ServerType};
+
+        // 0x02 is the welcome one way and the reply to it the other
+        let welcome = super::PatchWelcome::new(1, 2);
+        assert!(ServerPacket::parse(&welcome.as_bytes()) == Ok(ServerPacket::PatchWelcome(welcome.clone())));
+        assert!(ClientPacket::parse(&[4, 0, 2, 0]) == Ok(ClientPacket::PatchWelcomeReply(super::PatchWelcomeReply {})));
+        assert!(ClientPacket::parse(&welcome.as_bytes()).is_err());
+
+        let info = super::FileInfo::new("data.gsl", 3).unwrap();
+        let pkt = ServerPacket::from(info.clone());
+        assert!(pkt.cmd() == 0x0C);
+        assert!(pkt.as_bytes() == info.as_bytes());
+
+        assert!(ClientPacket::parse(&[8, 0, 0x55, 0, 1, 2, 3, 4]) == Ok(ClientPacket::Unknown {
+            cmd: 0x55,
+            flag: 0,
+            data: vec![8, 0, 0x55, 0, 1, 2, 3, 4],
+        }));
+        assert!(ClientPacket::parse(&[4, 0]).is_err());
+
+        // a bb login arriving at the patch server
+        assert!(ClientPacket::parse(&[0xB4, 0, 0x93, 0, 0, 0, 0, 0]) == Err(PacketParseError::WrongPacketForServerType {
+            packet: "Login",
+            server: ServerType::Login,
+        }));
+    }
+
+    #[test]
+    fn test_truncated() {
+        use super::PSOPacket;
+
+        // every prefix of a packet with its declared size fixed up so parsing gets past the header
+        fn truncated(bytes: &[u8]) -> Vec<Vec<u8>> {
+            (0..bytes.len()).map(|len| {
+                let mut short = bytes[..len].to_vec();
+                if len >= 2 {
+                    short[..2].copy_from_slice(&(len as u16).to_le_bytes());
+                }
+                short
+            }).collect()
+        }
+
+        for short in truncated(&super::PatchWelcome::new(1, 2).as_bytes()) {
+            assert!(super::PatchWelcome::from_bytes(&short).is_err());
+        }
+        for short in truncated(&super::FileInfo::new("data.gsl", 3).unwrap().as_bytes()) {
+            assert!(super::FileInfo::from_bytes(&short).is_err());
+        }
+        for short in truncated(&super::Message::new("hello".to_string()).as_bytes()) {
+            // an odd byte count splits a utf-16 code unit
+            assert!(super::Message::from_bytes(&short).is_ok() == (short.len() >= 4 && short.len().is_multiple_of(2)));
+        }
+        let mut chunk = vec![0x18, 0x00, 0x07, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0];
+        chunk.extend_from_slice(b"chunk\0\0\0");
+        for short in truncated(&chunk) {
+            assert!(super::FileSend::from_bytes(&short).is_err());
+        }
+    }
 }
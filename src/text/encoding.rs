@@ -0,0 +1,195 @@
+// pc, dc and gc send 8 bit text as iso-8859-1 or shift-jis. strings can switch between the two
+// with a \tE or \tJ marker, so marked text carries its own language.
+
+use encoding_rs::SHIFT_JIS;
+
+use crate::text::TextError;
+
+const MARKER_ENGLISH: &[u8] = b"\tE";
+const MARKER_JAPANESE: &[u8] = b"\tJ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf16,
+    ShiftJis,
+    Latin1,
+    // latin1 until the first marker. markers are dropped when decoding. encoding starts with the
+    // marker for the first non-ascii character and adds one each time the text switches script.
+    Marked,
+}
+
+impl Encoding {
+    // never fails, anything that doesn't decode comes out as U+FFFD
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf16 => {
+                let units = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>();
+                String::from_utf16_lossy(&units)
+            },
+            Encoding::ShiftJis => SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+            Encoding::Marked => decode_marked(bytes),
+        }
+    }
+
+    pub fn encode(self, s: &str) -> Result<Vec<u8>, TextError> {
+        self.encode_with(s, false)
+    }
+
+    // unencodable characters become '?'
+    pub fn encode_lossy(self, s: &str) -> Vec<u8> {
+        self.encode_with(s, true).unwrap_or_default()
+    }
+
+    fn encode_with(self, s: &str, lossy: bool) -> Result<Vec<u8>, TextError> {
+        match self {
+            Encoding::Utf16 => Ok(s.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()),
+            Encoding::ShiftJis => encode_chars(s, lossy, encode_sjis_char),
+            Encoding::Latin1 => encode_chars(s, lossy, encode_latin1_char),
+            Encoding::Marked => encode_marked(s, lossy),
+        }
+    }
+}
+
+// pushes c onto buf and returns true, or returns false if it can't be encoded
+type EncodeChar = fn(char, &mut Vec<u8>) -> bool;
+
+fn encode_latin1_char(c: char, buf: &mut Vec<u8>) -> bool {
+    if (c as u32) <= 0xFF {
+        buf.push(c as u8);
+        true
+    }
+    else {
+        false
+    }
+}
+
+fn encode_sjis_char(c: char, buf: &mut Vec<u8>) -> bool {
+    let mut utf8 = [0u8; 4];
+    let (bytes, _, had_errors) = SHIFT_JIS.encode(c.encode_utf8(&mut utf8));
+    if !had_errors {
+        buf.extend_from_slice(&bytes);
+    }
+    !had_errors
+}
+
+fn encode_chars(s: &str, lossy: bool, encode_char: EncodeChar) -> Result<Vec<u8>, TextError> {
+    let mut buf = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        if !encode_char(c, &mut buf) {
+            if !lossy {
+                return Err(TextError::Unencodable(c));
+            }
+            buf.push(b'?');
+        }
+    }
+    Ok(buf)
+}
+
+const SCRIPTS: [(&[u8], EncodeChar); 2] = [
+    (MARKER_ENGLISH, encode_latin1_char),
+    (MARKER_JAPANESE, encode_sjis_char),
+];
+
+fn fits(script: usize, c: char) -> bool {
+    (SCRIPTS[script].1)(c, &mut Vec::new())
+}
+
+// ascii fits both scripts so it never forces a switch. a character neither can encode stays in
+// the current run as '?' when lossy.
+fn encode_marked(s: &str, lossy: bool) -> Result<Vec<u8>, TextError> {
+    let mut current = s.chars()
+        .find(|c| !c.is_ascii())
+        .and_then(|c| (0..SCRIPTS.len()).find(|script| fits(*script, c)))
+        .unwrap_or(0);
+
+    let mut buf = SCRIPTS[current].0.to_vec();
+    for c in s.chars() {
+        if (SCRIPTS[current].1)(c, &mut buf) {
+            continue;
+        }
+        match (0..SCRIPTS.len()).find(|script| *script != current && fits(*script, c)) {
+            Some(script) => {
+                current = script;
+                buf.extend_from_slice(SCRIPTS[current].0);
+                (SCRIPTS[current].1)(c, &mut buf);
+            },
+            None if lossy => buf.push(b'?'),
+            None => return Err(TextError::Unencodable(c)),
+        }
+    }
+    Ok(buf)
+}
+
+// a tab can't show up inside a shift-jis character, so markers can be found byte by byte
+fn decode_marked(bytes: &[u8]) -> String {
+    let mut result = String::new();
+    let mut current = Encoding::Latin1;
+    let mut start = 0;
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        let next = match &bytes[i..i+2] {
+            MARKER_ENGLISH => Some(Encoding::Latin1),
+            MARKER_JAPANESE => Some(Encoding::ShiftJis),
+            _ => None,
+        };
+        match next {
+            Some(next) => {
+                result.push_str(&current.decode(&bytes[start..i]));
+                current = next;
+                i += 2;
+                start = i;
+            },
+            None => i += 1,
+        }
+    }
+    result.push_str(&current.decode(&bytes[start..]));
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+    use crate::text::TextError;
+
+    #[test]
+    fn test_sjis() {
+        let bytes = Encoding::ShiftJis.encode("ハンター").unwrap();
+        assert!(bytes == [0x83, 0x6E, 0x83, 0x93, 0x83, 0x5E, 0x81, 0x5B]);
+        assert!(Encoding::ShiftJis.decode(&bytes) == "ハンター");
+        assert!(Encoding::ShiftJis.encode("é") == Err(TextError::Unencodable('é')));
+    }
+
+    #[test]
+    fn test_latin1() {
+        assert!(Encoding::Latin1.encode("café").unwrap() == b"caf\xE9");
+        assert!(Encoding::Latin1.decode(b"caf\xE9") == "café");
+        assert!(Encoding::Latin1.encode("ハ") == Err(TextError::Unencodable('ハ')));
+        assert!(Encoding::Latin1.encode_lossy("aハb") == b"a?b");
+    }
+
+    #[test]
+    fn test_marked() {
+        assert!(Encoding::Marked.encode("café").unwrap() == b"\tEcaf\xE9");
+        assert!(Encoding::Marked.encode("ハンター").unwrap() == b"\tJ\x83\x6E\x83\x93\x83\x5E\x81\x5B");
+        assert!(Encoding::Marked.decode(b"\tJ\x83\x6E\x83\x93\x83\x5E\x81\x5B") == "ハンター");
+
+        // switching partway and text with no marker at all
+        assert!(Encoding::Marked.decode(b"\tEcaf\xE9 \tJ\x83\x6E") == "café ハ");
+        assert!(Encoding::Marked.decode(b"plain\xE9") == "plainé");
+
+        // a marker goes in each time the script changes, so mixed text survives a round trip
+        assert!(Encoding::Marked.encode(&Encoding::Marked.decode(b"\tEcaf\xE9 \tJ\x83\x6E")).unwrap() == b"\tEcaf\xE9 \tJ\x83\x6E");
+        let mixed = b"\tEcaf\xE9 \tJ\x83\x6E na\tE\xEFve";
+        assert!(Encoding::Marked.decode(mixed) == "café ハ naïve");
+        assert!(Encoding::Marked.encode("café ハ naïve").unwrap() == mixed);
+        assert!(Encoding::Marked.encode("ハ é").unwrap() == b"\tJ\x83\x6E \tE\xE9");
+        assert!(Encoding::Marked.encode("aハ\u{1F600}") == Err(TextError::Unencodable('\u{1F600}')));
+        assert!(Encoding::Marked.encode_lossy("aハ\u{1F600}") == b"\tJa\x83\x6E?");
+
+        // whatever the text came in as, it can go back out as utf-16
+        let text = Encoding::Marked.decode(b"\tJ\x83\x6E");
+        assert!(Encoding::Utf16.encode(&text).unwrap() == [0xCF, 0x30]);
+    }
+}
@@ -0,0 +1,228 @@
+// fixed width, nul padded strings the way packets carry them. building one from a rust string
+// fails if it doesn't fit with room left for the terminator, so everything this crate sends is
+// terminated. text read off the wire is kept as is and may fill the whole field.
+
+pub mod encoding;
+
+use std::io::Read;
+
+use crate::{PSOPacketData, PacketParseError};
+
+pub use crate::text::encoding::Encoding;
+
+#[derive(Debug, PartialEq)]
+pub enum TextError {
+    TooLong {
+        max: usize,
+        len: usize,
+    },
+    InvalidUtf8,
+    InvalidUtf16,
+    Unencodable(char),
+}
+
+impl std::fmt::Display for TextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TextError::TooLong { max, len } => write!(f, "text is {} units long, the field holds at most {}", len, max),
+            TextError::InvalidUtf8 => write!(f, "text is not valid utf-8"),
+            TextError::InvalidUtf16 => write!(f, "text is not valid utf-16"),
+            TextError::Unencodable(c) => write!(f, "{:?} can't be encoded", c),
+        }
+    }
+}
+
+impl std::error::Error for TextError {}
+
+
+// 8 bit text in a [u8; N]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FixedStr<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> FixedStr<N> {
+    pub fn new(s: &str) -> Result<FixedStr<N>, TextError> {
+        FixedStr::from_slice(s.as_bytes())
+    }
+
+    pub fn from_slice(s: &[u8]) -> Result<FixedStr<N>, TextError> {
+        if s.len() >= N {
+            return Err(TextError::TooLong {
+                max: N.saturating_sub(1),
+                len: s.len(),
+            });
+        }
+        let mut bytes = [0u8; N];
+        bytes[..s.len()].copy_from_slice(s);
+        Ok(FixedStr {
+            bytes,
+        })
+    }
+
+    // the raw field, terminated or not
+    pub fn from_bytes(bytes: [u8; N]) -> FixedStr<N> {
+        FixedStr {
+            bytes,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.bytes
+    }
+
+    // everything before the terminator
+    pub fn text(&self) -> &[u8] {
+        let len = self.bytes.iter().position(|b| *b == 0).unwrap_or(N);
+        &self.bytes[..len]
+    }
+
+    pub fn as_str(&self) -> Result<&str, TextError> {
+        std::str::from_utf8(self.text()).map_err(|_| TextError::InvalidUtf8)
+    }
+
+    pub fn encode(s: &str, encoding: Encoding) -> Result<FixedStr<N>, TextError> {
+        FixedStr::from_slice(&encoding.encode(s)?)
+    }
+
+    pub fn decode(&self, encoding: Encoding) -> String {
+        encoding.decode(self.text())
+    }
+}
+
+impl<const N: usize> Default for FixedStr<N> {
+    fn default() -> FixedStr<N> {
+        FixedStr {
+            bytes: [0; N],
+        }
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for FixedStr<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.as_str() {
+            Ok(s) => write!(f, "{:?}", s),
+            Err(_) => write!(f, "{:?}", self.text()),
+        }
+    }
+}
+
+impl<const N: usize> PSOPacketData for FixedStr<N> {
+    fn from_bytes(cur: &mut std::io::Cursor<&[u8]>) -> Result<FixedStr<N>, PacketParseError> {
+        let mut bytes = [0u8; N];
+        if cur.read_exact(&mut bytes).is_err() {
+            return Err(PacketParseError::NotEnoughBytes);
+        }
+        Ok(FixedStr::from_bytes(bytes))
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        self.bytes.to_vec()
+    }
+}
+
+
+// utf-16 text in a [u16; N]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FixedUtf16<const N: usize> {
+    units: [u16; N],
+}
+
+impl<const N: usize> FixedUtf16<N> {
+    pub fn new(s: &str) -> Result<FixedUtf16<N>, TextError> {
+        let len = s.encode_utf16().count();
+        if len >= N {
+            return Err(TextError::TooLong {
+                max: N.saturating_sub(1),
+                len,
+            });
+        }
+        let mut units = [0u16; N];
+        for (dst, src) in units.iter_mut().zip(s.encode_utf16()) {
+            *dst = src;
+        }
+        Ok(FixedUtf16 {
+            units,
+        })
+    }
+
+    pub fn from_units(units: [u16; N]) -> FixedUtf16<N> {
+        FixedUtf16 {
+            units,
+        }
+    }
+
+    pub fn as_units(&self) -> &[u16; N] {
+        &self.units
+    }
+
+    pub fn text(&self) -> &[u16] {
+        let len = self.units.iter().position(|c| *c == 0).unwrap_or(N);
+        &self.units[..len]
+    }
+
+    pub fn as_string(&self) -> Result<String, TextError> {
+        String::from_utf16(self.text()).map_err(|_| TextError::InvalidUtf16)
+    }
+}
+
+impl<const N: usize> Default for FixedUtf16<N> {
+    fn default() -> FixedUtf16<N> {
+        FixedUtf16 {
+            units: [0; N],
+        }
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for FixedUtf16<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", String::from_utf16_lossy(self.text()))
+    }
+}
+
+impl<const N: usize> PSOPacketData for FixedUtf16<N> {
+    fn from_bytes(cur: &mut std::io::Cursor<&[u8]>) -> Result<FixedUtf16<N>, PacketParseError> {
+        let mut units = [0u16; N];
+        for unit in units.iter_mut() {
+            let mut b = [0u8; 2];
+            if cur.read_exact(&mut b).is_err() {
+                return Err(PacketParseError::NotEnoughBytes);
+            }
+            *unit = u16::from_le_bytes(b);
+        }
+        Ok(FixedUtf16::from_units(units))
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        self.units.iter().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedStr, FixedUtf16, TextError};
+
+    #[test]
+    fn test_fixed_str() {
+        let s = FixedStr::<8>::new("data").unwrap();
+        assert!(s.as_bytes() == b"data\0\0\0\0");
+        assert!(s.as_str() == Ok("data"));
+        assert!(FixedStr::<8>::new("1234567").unwrap().as_bytes()[7] == 0);
+        assert!(FixedStr::<8>::new("12345678") == Err(TextError::TooLong { max: 7, len: 8 }));
+
+        // straight off the wire, no terminator
+        let s = FixedStr::from_bytes(*b"full");
+        assert!(s.as_str() == Ok("full"));
+        assert!(FixedStr::from_bytes([0xFF, 0, 0, 0]).as_str() == Err(TextError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_fixed_utf16() {
+        let s = FixedUtf16::<4>::new("ab").unwrap();
+        assert!(s.as_units() == &[0x61, 0x62, 0, 0]);
+        assert!(s.as_string() == Ok("ab".to_string()));
+        assert!(FixedUtf16::<4>::new("abcd") == Err(TextError::TooLong { max: 3, len: 4 }));
+        assert!(FixedUtf16::from_units([0xD800, 0]).as_string() == Err(TextError::InvalidUtf16));
+    }
+}
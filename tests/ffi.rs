@@ -0,0 +1,53 @@
+// builds tests/ffi/cipher_test.c against a cdylib of libpso and the header build.rs generated, and
+// checks that the copy of that header in include/ is current
+#![cfg(feature = "ffi")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_c_bindings() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // libpso is only an rlib, so build the shared library the same way a c user would. it gets its
+    // own target dir so this doesn't wait on the lock the outer cargo holds
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("rustc")
+        .arg("--manifest-path").arg(manifest_dir.join("Cargo.toml"))
+        .arg("--lib")
+        .arg("--features").arg("ffi")
+        .arg("--crate-type").arg("cdylib")
+        .arg("--target-dir").arg(&target_dir)
+        .status()
+        .expect("unable to run cargo");
+    assert!(status.success());
+
+    let lib_dir = target_dir.join("debug");
+    let exe = lib_dir.join("libpso_c_test");
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(manifest_dir.join("tests").join("ffi").join("cipher_test.c"))
+        .arg("-I").arg(env!("OUT_DIR"))
+        .arg("-L").arg(&lib_dir)
+        .arg("-llibpso")
+        .arg("-o").arg(&exe)
+        .status()
+        .expect("unable to run the c compiler");
+    assert!(status.success());
+
+    let status = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_header_up_to_date() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let generated = std::fs::read_to_string(PathBuf::from(env!("OUT_DIR")).join("libpso.h")).unwrap();
+    let checked_in = std::fs::read_to_string(manifest_dir.join("include").join("libpso.h")).unwrap();
+    assert!(generated == checked_in,
+            "include/libpso.h is stale, regenerate it with \
+             `cbindgen --config cbindgen.toml --output include/libpso.h src/ffi.rs`");
+}
@@ -0,0 +1,60 @@
+#include <stdio.h>
+#include <string.h>
+
+#include "libpso.h"
+
+#define CHECK(cond) do { if (!(cond)) { fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond); return 1; } } while (0)
+
+static int round_trip(PSOCipherHandle *in, PSOCipherHandle *out, size_t len) {
+    uint8_t orig[256];
+    uint8_t data[256];
+    size_t i;
+
+    for (i = 0; i < len; i++) {
+        orig[i] = (uint8_t)(i * 7 + 3);
+    }
+    memcpy(data, orig, len);
+
+    CHECK(pso_cipher_encrypt(in, data, len) == PSO_OK);
+    CHECK(memcmp(data, orig, len) != 0);
+    CHECK(pso_cipher_decrypt(out, data, len) == PSO_OK);
+    CHECK(memcmp(data, orig, len) == 0);
+    return 0;
+}
+
+int main(void) {
+    uint8_t key_table[0x1048];
+    uint8_t seed[48];
+    uint8_t junk[12] = {0};
+    size_t i;
+
+    PSOCipherHandle *pc_in = pso_pc_cipher_new(0x12345678);
+    PSOCipherHandle *pc_out = pso_pc_cipher_new(0x12345678);
+    CHECK(pc_in != NULL && pc_out != NULL);
+    CHECK(pso_cipher_header_size(pc_in) == 4);
+    CHECK(round_trip(pc_in, pc_out, 64) == 0);
+    CHECK(pso_cipher_encrypt(pc_in, junk, 6) == PSO_ERROR_INVALID_SIZE);
+    CHECK(pso_cipher_encrypt(NULL, junk, 4) == PSO_ERROR_NULL_POINTER);
+    pso_cipher_free(pc_in);
+    pso_cipher_free(pc_out);
+
+    for (i = 0; i < sizeof(key_table); i++) {
+        key_table[i] = (uint8_t)(i * 31 + 1);
+    }
+    for (i = 0; i < sizeof(seed); i++) {
+        seed[i] = (uint8_t)i;
+    }
+
+    PSOCipherHandle *bb_in = pso_bb_cipher_new(key_table, sizeof(key_table), seed);
+    PSOCipherHandle *bb_out = pso_bb_cipher_new(key_table, sizeof(key_table), seed);
+    CHECK(bb_in != NULL && bb_out != NULL);
+    CHECK(pso_cipher_header_size(bb_in) == 8);
+    CHECK(round_trip(bb_in, bb_out, 128) == 0);
+    CHECK(pso_cipher_encrypt(bb_in, junk, 12) == PSO_ERROR_INVALID_SIZE);
+    CHECK(pso_bb_cipher_new(key_table, 16, seed) == NULL);
+    pso_cipher_free(bb_in);
+    pso_cipher_free(bb_out);
+    pso_cipher_free(NULL);
+
+    return 0;
+}
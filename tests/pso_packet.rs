@@ -0,0 +1,376 @@
+// #[pso_packet] and the data derives used from outside the crate, the way packets defined
+// downstream see them
+
+#[test]
+fn test_header_layouts() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x60, header = "pc")]
+    struct PCCommand {
+        flag: u8,
+        data: u16,
+    }
+
+    #[pso_packet(0x60, header = "gc")]
+    struct GCCommand {
+        flag: u8,
+        data: u16,
+    }
+
+    #[pso_packet(0x60, header = "dc")]
+    struct DCCommand {
+        data: u32,
+    }
+
+    let pc = PCCommand {
+        flag: 3,
+        data: 0x1234,
+    };
+    assert!(pc.as_bytes() == [8, 0, 0x60, 3, 0x34, 0x12, 0, 0]);
+    assert!(PCCommand::from_bytes(&pc.as_bytes()).unwrap() == pc);
+
+    let gc = GCCommand {
+        flag: 3,
+        data: 0x1234,
+    };
+    assert!(gc.as_bytes() == [0x60, 3, 8, 0, 0x34, 0x12, 0, 0]);
+    assert!(GCCommand::from_bytes(&gc.as_bytes()).unwrap() == gc);
+    assert!(matches!(PCCommand::from_bytes(&gc.as_bytes()), Err(PacketParseError::WrongPacketCommand { got: 8, .. })));
+
+    // no flag field, whatever the header carries is dropped
+    let dc = DCCommand::from_bytes(&[0x60, 9, 8, 0, 1, 0, 0, 0]).unwrap();
+    assert!(dc.data == 1);
+    assert!(dc.as_bytes() == [0x60, 0, 8, 0, 1, 0, 0, 0]);
+}
+
+#[test]
+#[should_panic(expected = "does not fit the u8 flag field")]
+fn test_flag_out_of_range() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x60, header = "gc")]
+    struct WideFlag {
+        flag: u32,
+    }
+
+    WideFlag {
+        flag: 0x100,
+    }.as_bytes();
+}
+
+#[test]
+#[should_panic(expected = "does not fit the u16 size field")]
+fn test_packet_too_large() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x13)]
+    struct Big {
+        #[pso(remaining)]
+        data: Vec<u8>,
+    }
+
+    Big {
+        data: vec![0; 0x10000],
+    }.as_bytes();
+}
+
+#[test]
+fn test_numeric_fields() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x01)]
+    struct Numbers {
+        a: i8,
+        b: i16,
+        c: i32,
+        d: u64,
+        e: i64,
+        f: f32,
+        g: [u32; 2],
+        h: [i16; 3],
+        i: [f32; 2],
+    }
+
+    let pkt = Numbers {
+        a: -1,
+        b: -2,
+        c: -3,
+        d: 0x0102030405060708,
+        e: -4,
+        f: 1.5,
+        g: [5, 6],
+        h: [-7, 8, -9],
+        i: [0.25, -2.0],
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes.len() == 4 + 1 + 2 + 4 + 8 + 8 + 4 + 8 + 6 + 8 + 3);
+    assert!(bytes[4..11] == [0xFF, 0xFE, 0xFF, 0xFD, 0xFF, 0xFF, 0xFF]);
+    assert!(bytes[27..31] == 1.5f32.to_le_bytes());
+    assert!(bytes[31..39] == [5, 0, 0, 0, 6, 0, 0, 0]);
+    assert!(Numbers::from_bytes(&bytes).unwrap() == pkt);
+}
+
+#[test]
+fn test_nested_data() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PSOPacketData, PacketParseError};
+
+    #[derive(PSOPacketData, Debug, Clone, PartialEq)]
+    struct MenuEntry {
+        id: u32,
+        name: [u8; 4],
+    }
+
+    #[pso_packet(0x07, header = "bb")]
+    struct Menu {
+        flag: u32,
+        selected: MenuEntry,
+        entries: [MenuEntry; 3],
+    }
+
+    let entry = |id: u32| MenuEntry {
+        id,
+        name: [b'a' + id as u8; 4],
+    };
+    let pkt = Menu {
+        flag: 3,
+        selected: entry(1),
+        entries: [entry(0), entry(1), entry(2)],
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes.len() == 8 + 8 * 4);
+    assert!(bytes[8 + 8 * 3..] == [2, 0, 0, 0, b'c', b'c', b'c', b'c']);
+    assert!(Menu::from_bytes(&bytes).unwrap() == pkt);
+
+    let mut truncated = bytes[..0x26].to_vec();
+    truncated[0] = 0x26;
+    let err = Menu::from_bytes(&truncated).unwrap_err();
+    assert!(err == PacketParseError::Field {
+        packet: "Menu",
+        field: "entries",
+        offset: 0x10,
+        error: Box::new(PacketParseError::Field {
+            packet: "MenuEntry",
+            field: "name",
+            offset: 0x24,
+            error: Box::new(PacketParseError::NotEnoughBytes),
+        }),
+    });
+}
+
+#[test]
+fn test_vec_fields() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PSOPacketData, PacketParseError};
+
+    #[derive(PSOPacketData, Debug, Clone, PartialEq)]
+    struct Entry {
+        id: u32,
+        name: [u8; 4],
+    }
+
+    #[pso_packet(0x08, header = "bb")]
+    struct HeaderCount {
+        #[pso(count = "header.flag")]
+        entries: Vec<Entry>,
+    }
+
+    #[pso_packet(0x09)]
+    struct FieldCount {
+        unknown: u16,
+        entry_count: u16,
+        #[pso(count = "entry_count")]
+        entries: Vec<u32>,
+    }
+
+    #[pso_packet(0x0A, header = "bb")]
+    struct Remaining {
+        id: u32,
+        #[pso(remaining)]
+        entries: Vec<Entry>,
+    }
+
+    let entries = vec![Entry { id: 1, name: *b"abcd" }, Entry { id: 2, name: *b"efgh" }];
+    let pkt = HeaderCount {
+        entries: entries.clone(),
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes[..8] == [0x18, 0, 0x08, 0, 2, 0, 0, 0]);
+    assert!(HeaderCount::from_bytes(&bytes).unwrap() == pkt);
+
+    let pkt = FieldCount {
+        unknown: 7,
+        entry_count: 0,
+        entries: vec![1, 2, 3],
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes[4..8] == [7, 0, 3, 0]);
+    let parsed = FieldCount::from_bytes(&bytes).unwrap();
+    assert!(parsed.entry_count == 3 && parsed.entries == pkt.entries);
+
+    // 4 bytes of padding after the last entry
+    let pkt = Remaining {
+        id: 5,
+        entries: entries.clone(),
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes.len() == 0x20);
+    assert!(Remaining::from_bytes(&bytes).unwrap() == pkt);
+
+    // claims one more entry than there is
+    let mut bytes = FieldCount {
+        unknown: 0,
+        entry_count: 0,
+        entries: vec![1],
+    }.as_bytes();
+    bytes[6] = 2;
+    assert!(matches!(FieldCount::from_bytes(&bytes), Err(PacketParseError::Field { field: "entries", .. })));
+}
+
+#[test]
+#[should_panic(expected = "300 entries in entries do not fit the u8 count field entry_count")]
+fn test_count_out_of_range() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x09)]
+    struct ByteCount {
+        entry_count: u8,
+        #[pso(count = "entry_count")]
+        entries: Vec<u8>,
+    }
+
+    ByteCount {
+        entry_count: 0,
+        entries: vec![0; 300],
+    }.as_bytes();
+}
+
+#[test]
+fn test_enum_fields() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PSOPacketData, PSOEnum, PacketParseError};
+
+    #[derive(PSOEnum, Debug, Clone, PartialEq)]
+    #[repr(u8)]
+    enum Section {
+        Viridia,
+        Greennill,
+        Skyly = 5,
+        Bluefull,
+        #[pso(unknown)]
+        Unknown(u8),
+    }
+
+    #[derive(PSOEnum, Debug, Clone, PartialEq)]
+    #[repr(u16)]
+    enum Strict {
+        A = 0x100,
+        B,
+    }
+
+    #[pso_packet(0x0B)]
+    struct Enums {
+        sections: [Section; 5],
+        strict: Strict,
+        padding: u8,
+    }
+
+    let pkt = Enums {
+        sections: [Section::Viridia, Section::Greennill, Section::Skyly, Section::Bluefull, Section::Unknown(0x80)],
+        strict: Strict::B,
+        padding: 0,
+    };
+    let bytes = pkt.as_bytes();
+    assert!(bytes[4..] == [0, 1, 5, 6, 0x80, 0x01, 0x01, 0]);
+    assert!(Enums::from_bytes(&bytes).unwrap() == pkt);
+
+    let mut bytes = bytes;
+    bytes[9] = 0x02;
+    assert!(matches!(Enums::from_bytes(&bytes), Err(PacketParseError::Field { field: "strict", .. })));
+}
+
+#[test]
+fn test_encoded_strings() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PacketParseError};
+
+    #[pso_packet(0x06, header = "gc")]
+    struct GCChat {
+        guildcard: u32,
+        #[pso(encoding = "marked")]
+        msg: String,
+    }
+
+    #[pso_packet(0x06, header = "pc")]
+    struct PCChat {
+        guildcard: u32,
+        #[pso(encoding = "utf16")]
+        msg: String,
+    }
+
+    let mut bytes = vec![0x06, 0, 0x10, 0, 1, 0, 0, 0];
+    bytes.extend_from_slice(b"\tJ\x83\x6E\x83\x93\0\0");
+    let chat = GCChat::from_bytes(&bytes).unwrap();
+    assert!(chat.msg == "ハン\0\0");
+    assert!(chat.as_bytes() == bytes);
+
+    let pc = PCChat {
+        guildcard: chat.guildcard,
+        msg: chat.msg,
+    };
+    assert!(pc.as_bytes()[8..] == [0xCF, 0x30, 0xF3, 0x30, 0, 0, 0, 0]);
+
+    // half a code unit at the end, like a plain String or a FixedUtf16 cut short
+    let bytes = [11, 0, 0x06, 0, 1, 0, 0, 0, 0xCF, 0x30, 0xF3];
+    assert!(matches!(PCChat::from_bytes(&bytes), Err(PacketParseError::Field { field: "msg", .. })));
+}
+
+#[test]
+fn test_encoded_fixed_strings() {
+    use std::io::Read;
+    use psopacket::pso_packet;
+    use libpso::{PSOPacket, PSOPacketData, PacketParseError};
+    use libpso::text::{FixedStr, TextError};
+
+    #[derive(PSOPacketData, Clone, Debug, PartialEq)]
+    struct Entry {
+        #[pso(encoding = "sjis")]
+        name: FixedStr<8>,
+    }
+
+    #[pso_packet(0x61, header = "gc")]
+    struct GCName {
+        #[pso(encoding = "marked")]
+        name: FixedStr<12>,
+        entry: Entry,
+    }
+
+    let mut bytes = vec![0x61, 0, 0x18, 0];
+    bytes.extend_from_slice(b"\tEcaf\xE9 \tJ\x83\x6E\0");
+    bytes.extend_from_slice(b"\x83\x6E\x83\x93\0\0\0\0");
+    let mut pkt = GCName::from_bytes(&bytes).unwrap();
+    assert!(pkt.name_text() == "café ハ");
+    assert!(pkt.entry.name_text() == "ハン");
+    assert!(pkt.as_bytes() == bytes);
+
+    pkt.set_name_text("ハ é").unwrap();
+    assert!(pkt.name.text() == b"\tJ\x83\x6E \tE\xE9");
+    pkt.entry.set_name_text("abc").unwrap();
+    assert!(pkt.entry.name.text() == b"abc");
+    assert!(pkt.entry.set_name_text("é") == Err(TextError::Unencodable('é')));
+    assert!(pkt.set_name_text("much too long") == Err(TextError::TooLong { max: 11, len: 15 }));
+}